@@ -5,7 +5,7 @@ pub fn should_colour() -> bool {
     if cfg!(test) {
         false
     } else {
-        !matches!(std::env::var("T_NO_COLOUR").as_deref(), Ok("true" | "1"))
+        crate::config::get().colour.value
     }
 }
 
@@ -0,0 +1,655 @@
+use super::utility::SortOrder;
+
+use anyhow::anyhow;
+use chrono::format::{Item, StrftimeItems};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The configuration `main` settled on, or the defaults if it hasn't yet
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+pub fn init(config: Config) {
+    // Only main sets this, once, before anything reads it
+    let _ = CONFIG.set(config);
+}
+
+/// `$XDG_CONFIG_HOME/t/config.toml`, e.g.
+///
+/// ```toml
+/// todofile = "/home/me/todo.txt"
+/// autoarchive = false
+/// date_format = "%d %b"
+/// default_command = "listPriority"
+/// default_filters = ["-@someday"]
+/// confirm_above = 10
+///
+/// [views.work]
+/// command = "list"
+/// filters = ["+work", "-@waiting"]
+/// sort = "due"
+///
+/// [lists.team]
+/// todofile = "/shared/team/todo.txt"
+/// donefile = "/shared/team/done.txt"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    todofile: Option<PathBuf>,
+    donefile: Option<PathBuf>,
+    autoarchive: Option<bool>,
+    colour: Option<bool>,
+    date_format: Option<String>,
+    format: Option<OutputFormat>,
+    creation_date: Option<bool>,
+    auto_id: Option<bool>,
+    default_command: Option<ViewCommand>,
+    default_filters: Option<Vec<String>>,
+    confirm_above: Option<usize>,
+    #[serde(default)]
+    views: BTreeMap<String, SavedView>,
+    #[serde(default)]
+    lists: BTreeMap<String, ListFiles>,
+}
+
+/// A named todo list, chosen with `--list NAME`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListFiles {
+    pub todofile: PathBuf,
+    pub donefile: PathBuf,
+}
+
+/// A view command with its filters and sort order, run by `t view NAME`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedView {
+    #[serde(default)]
+    pub command: ViewCommand,
+    #[serde(default)]
+    pub filters: Vec<String>,
+    /// Order of tasks in `list`, `listPriority`, `listDone` and `noDate`
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// How far ahead `due` looks
+    #[serde(default)]
+    pub days: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ViewCommand {
+    #[default]
+    List,
+    ListPriority,
+    ListDone,
+    Due,
+    NoDate,
+    Upcoming,
+    ProjectView,
+    TagView,
+    Links,
+}
+
+impl fmt::Display for ViewCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ViewCommand::List => "list",
+            ViewCommand::ListPriority => "listPriority",
+            ViewCommand::ListDone => "listDone",
+            ViewCommand::Due => "due",
+            ViewCommand::NoDate => "noDate",
+            ViewCommand::Upcoming => "upcoming",
+            ViewCommand::ProjectView => "projectView",
+            ViewCommand::TagView => "tagView",
+            ViewCommand::Links => "links",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How views print tasks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// One JSON array
+    Json,
+    /// One JSON value per line
+    Jsonl,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Settings given on the command line, which beat everything else
+#[derive(Debug, Default)]
+pub struct Flags {
+    pub todofile: Option<PathBuf>,
+    pub donefile: Option<PathBuf>,
+    pub autoarchive: Option<bool>,
+    pub colour: Option<bool>,
+    pub date_format: Option<String>,
+    pub format: Option<OutputFormat>,
+    pub list: Option<String>,
+}
+
+/// Where a setting's value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    Flag(&'static str),
+    /// A `todo.txt` in the current directory or one of its parents
+    Found,
+    NextToTodofile,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "${}", var),
+            Source::Flag(flag) => write!(f, "{}", flag),
+            Source::Found => write!(f, "found from the current directory"),
+            Source::NextToTodofile => write!(f, "next to todofile"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    fn new(value: T) -> Setting<T> {
+        Setting {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    fn set(&mut self, value: Option<T>, source: Source) {
+        if let Some(value) = value {
+            *self = Setting { value, source };
+        }
+    }
+}
+
+/// Every setting, taken from the defaults, then the config file, then
+/// environment variables, then command line flags, each overriding the last.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub path: Option<PathBuf>,
+    pub todofile: Setting<Option<PathBuf>>,
+    pub donefile: Setting<Option<PathBuf>>,
+    pub autoarchive: Setting<bool>,
+    pub colour: Setting<bool>,
+    pub date_format: Setting<String>,
+    pub format: Setting<OutputFormat>,
+    pub creation_date: Setting<bool>,
+    pub auto_id: Setting<bool>,
+    pub default_command: Setting<ViewCommand>,
+    pub default_filters: Setting<Vec<String>>,
+    /// Ask before changing more tasks than this with `--where`
+    pub confirm_above: Setting<usize>,
+    pub views: BTreeMap<String, SavedView>,
+    /// Named lists; `default` is the todo and done files given without `--list`
+    pub lists: BTreeMap<String, ListFiles>,
+    /// The list chosen with `--list`, if any
+    pub list: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            path: None,
+            todofile: Setting::new(None),
+            donefile: Setting::new(None),
+            autoarchive: Setting::new(true),
+            colour: Setting::new(true),
+            date_format: Setting::new("%Y-%m-%d".to_string()),
+            format: Setting::new(OutputFormat::Text),
+            creation_date: Setting::new(true),
+            auto_id: Setting::new(true),
+            default_command: Setting::new(ViewCommand::List),
+            default_filters: Setting::new(Vec::new()),
+            confirm_above: Setting::new(5),
+            views: BTreeMap::new(),
+            lists: BTreeMap::new(),
+            list: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(flags: Flags) -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(path) = config_path() {
+            if let Ok(contents) = fs::read_to_string(&path) {
+                let file = toml::from_str(&contents)
+                    .map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))?;
+                config.apply_file(path, file);
+            }
+        }
+        config.apply_env(|var| env::var(var).ok());
+        let list = flags.list.clone();
+        config.apply_flags(flags);
+        let cwd = env::current_dir().ok();
+        config.find_files(cwd.as_deref(), data_dir());
+        config.choose_list(list)?;
+        let date_format = &config.date_format;
+        if StrftimeItems::new(&date_format.value).any(|item| item == Item::Error) {
+            return Err(anyhow!(
+                "Invalid date_format `{}` from {}",
+                date_format.value,
+                date_format.source
+            )
+            .into());
+        }
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, path: PathBuf, file: ConfigFile) {
+        let source = || Source::File(path.clone());
+        self.todofile.set(file.todofile.map(Some), source());
+        self.donefile.set(file.donefile.map(Some), source());
+        self.autoarchive.set(file.autoarchive, source());
+        self.colour.set(file.colour, source());
+        self.date_format.set(file.date_format, source());
+        self.format.set(file.format, source());
+        self.creation_date.set(file.creation_date, source());
+        self.auto_id.set(file.auto_id, source());
+        self.default_command.set(file.default_command, source());
+        self.default_filters.set(file.default_filters, source());
+        self.confirm_above.set(file.confirm_above, source());
+        self.views = file.views;
+        self.lists = file.lists;
+        self.path = Some(path);
+    }
+
+    // The variables t has always read, with their original meanings
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        let turned_off = |name| matches!(var(name).as_deref(), Some("true" | "1")).then_some(false);
+        let path = |name| var(name).map(|p| Some(PathBuf::from(p)));
+        self.todofile.set(path("TODOFILE"), Source::Env("TODOFILE"));
+        self.donefile.set(path("DONEFILE"), Source::Env("DONEFILE"));
+        self.autoarchive.set(
+            var("T_DONT_AUTOARCHIVE")
+                .filter(|v| !v.is_empty() && v != "false")
+                .map(|_| false),
+            Source::Env("T_DONT_AUTOARCHIVE"),
+        );
+        self.colour
+            .set(turned_off("T_NO_COLOUR"), Source::Env("T_NO_COLOUR"));
+        self.date_format
+            .set(var("T_DATE_FORMAT"), Source::Env("T_DATE_FORMAT"));
+        self.creation_date.set(
+            turned_off("T_NO_CREATION_DATE"),
+            Source::Env("T_NO_CREATION_DATE"),
+        );
+        self.auto_id
+            .set(turned_off("T_NO_AUTO_ID"), Source::Env("T_NO_AUTO_ID"));
+    }
+
+    fn apply_flags(&mut self, flags: Flags) {
+        self.todofile
+            .set(flags.todofile.map(Some), Source::Flag("--todofile"));
+        self.donefile
+            .set(flags.donefile.map(Some), Source::Flag("--donefile"));
+        let archive_flag = match flags.autoarchive {
+            Some(true) => "--auto-archive",
+            _ => "--no-auto-archive",
+        };
+        self.autoarchive
+            .set(flags.autoarchive, Source::Flag(archive_flag));
+        self.colour.set(flags.colour, Source::Flag("--no-colour"));
+        self.date_format
+            .set(flags.date_format, Source::Flag("--date-format"));
+        self.format.set(flags.format, Source::Flag("--format"));
+    }
+
+    /// If nothing says where the files are, use the nearest `todo.txt` going
+    /// up from `cwd`, or else one in `data_dir`. `done.txt` goes beside it.
+    fn find_files(&mut self, cwd: Option<&Path>, data_dir: Option<PathBuf>) {
+        if self.todofile.value.is_none() {
+            let found = cwd
+                .into_iter()
+                .flat_map(Path::ancestors)
+                .map(|dir| dir.join("todo.txt"))
+                .find(|path| path.is_file());
+            match found {
+                Some(path) => self.todofile.set(Some(Some(path)), Source::Found),
+                None => self.todofile.value = data_dir.map(|dir| dir.join("todo.txt")),
+            }
+        }
+        if self.donefile.value.is_none() {
+            let beside = self
+                .todofile
+                .value
+                .as_ref()
+                .map(|todofile| Some(todofile.with_file_name("done.txt")));
+            self.donefile.set(beside, Source::NextToTodofile);
+        }
+    }
+
+    /// Switch to a named list, after noting the files it replaces as `default`
+    fn choose_list(&mut self, list: Option<String>) -> Result<()> {
+        if let (Some(todofile), Some(donefile)) = (&self.todofile.value, &self.donefile.value) {
+            if !self.lists.contains_key("default") {
+                let files = ListFiles {
+                    todofile: todofile.clone(),
+                    donefile: donefile.clone(),
+                };
+                self.lists.insert("default".to_string(), files);
+            }
+        }
+        if let Some(name) = list {
+            let files = self.list_files(&name)?.clone();
+            self.todofile
+                .set(Some(Some(files.todofile)), Source::Flag("--list"));
+            self.donefile
+                .set(Some(Some(files.donefile)), Source::Flag("--list"));
+            self.list = Some(name);
+        }
+        Ok(())
+    }
+
+    pub fn todofile(&self) -> Result<&Path> {
+        self.todofile.value.as_deref().ok_or_else(|| {
+            anyhow!("No todo file: set TODOFILE, `todofile` in the config file, or --todofile")
+                .into()
+        })
+    }
+
+    pub fn donefile(&self) -> Result<&Path> {
+        self.donefile.value.as_deref().ok_or_else(|| {
+            anyhow!("No done file: set DONEFILE, `donefile` in the config file, or --donefile")
+                .into()
+        })
+    }
+
+    pub fn view(&self, name: &str) -> Result<&SavedView> {
+        self.views.get(name).ok_or_else(|| {
+            let known = self.views.keys().cloned().collect::<Vec<_>>().join(", ");
+            anyhow!("No view called `{}`. Views: {}", name, known).into()
+        })
+    }
+
+    pub fn list_files(&self, name: &str) -> Result<&ListFiles> {
+        self.lists.get(name).ok_or_else(|| {
+            let known = self.lists.keys().cloned().collect::<Vec<_>>().join(", ");
+            anyhow!("No list called `{}`. Lists: {}", name, known).into()
+        })
+    }
+
+    /// Show each setting, its value, and where that came from
+    pub fn print(&self) -> Result<()> {
+        let path = |p: &Option<PathBuf>| match p {
+            Some(p) => p.display().to_string(),
+            None => "(unset)".to_string(),
+        };
+        let rows = [
+            (
+                "todofile",
+                path(&self.todofile.value),
+                &self.todofile.source,
+            ),
+            (
+                "donefile",
+                path(&self.donefile.value),
+                &self.donefile.source,
+            ),
+            (
+                "autoarchive",
+                self.autoarchive.value.to_string(),
+                &self.autoarchive.source,
+            ),
+            ("colour", self.colour.value.to_string(), &self.colour.source),
+            (
+                "date_format",
+                self.date_format.value.clone(),
+                &self.date_format.source,
+            ),
+            ("format", self.format.value.to_string(), &self.format.source),
+            (
+                "creation_date",
+                self.creation_date.value.to_string(),
+                &self.creation_date.source,
+            ),
+            (
+                "auto_id",
+                self.auto_id.value.to_string(),
+                &self.auto_id.source,
+            ),
+            (
+                "default_command",
+                self.default_command.value.to_string(),
+                &self.default_command.source,
+            ),
+            (
+                "default_filters",
+                self.default_filters.value.join(" "),
+                &self.default_filters.source,
+            ),
+            (
+                "confirm_above",
+                self.confirm_above.value.to_string(),
+                &self.confirm_above.source,
+            ),
+        ];
+        println!("config file: {}", path(&self.path));
+        for (name, value, source) in rows.iter() {
+            println!("{:<16} {:<32} ({})", name, value, source);
+        }
+        for (name, files) in &self.lists {
+            let current = if self.list.as_deref() == Some(name) {
+                " (current)"
+            } else {
+                ""
+            };
+            println!(
+                "{:<16} {} {}{}",
+                format!("list {}", name),
+                files.todofile.display(),
+                files.donefile.display(),
+                current
+            );
+        }
+        if !self.views.is_empty() {
+            let views = self.views.keys().cloned().collect::<Vec<_>>();
+            println!("{:<16} {}", "views", views.join(" "));
+        }
+        Ok(())
+    }
+}
+
+/// Where the todo file lives when there's no other to be found
+fn data_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(dir.join("t"))
+}
+
+fn config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("t").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str, env: &[(&str, &str)], flags: Flags) -> Config {
+        let mut config = Config::default();
+        config.apply_file(PathBuf::from("config.toml"), toml::from_str(toml).unwrap());
+        config.apply_env(|var| {
+            env.iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value.to_string())
+        });
+        let list = flags.list.clone();
+        config.apply_flags(flags);
+        config.find_files(None, Some(PathBuf::from("data")));
+        config.choose_list(list).unwrap();
+        config
+    }
+
+    #[test]
+    fn can_parse_views() {
+        let config = config(
+            r#"
+            [views.work]
+            command = "listPriority"
+            filters = ["+work", "-@waiting"]
+            sort = "due"
+
+            [views.soon]
+            command = "due"
+            days = 7
+            "#,
+            &[],
+            Flags::default(),
+        );
+        assert_eq!(
+            config.view("work").unwrap(),
+            &SavedView {
+                command: ViewCommand::ListPriority,
+                filters: vec!["+work".to_string(), "-@waiting".to_string()],
+                sort: SortOrder::Due,
+                days: 0,
+            }
+        );
+        assert_eq!(config.view("soon").unwrap().sort, SortOrder::Priority);
+        assert!(config.view("home").is_err());
+        assert!(toml::from_str::<ConfigFile>("[views.x]\ncommand = \"explode\"").is_err());
+        assert!(toml::from_str::<ConfigFile>("colour = \"yes\"").is_err());
+    }
+
+    #[test]
+    fn flags_beat_env_beat_file() {
+        let file = r#"
+            todofile = "file-todo.txt"
+            donefile = "file-done.txt"
+            autoarchive = true
+            date_format = "%d/%m"
+            default_command = "noDate"
+            confirm_above = 10
+        "#;
+        let env = [
+            ("TODOFILE", "env-todo.txt"),
+            ("T_DONT_AUTOARCHIVE", "1"),
+            ("T_NO_COLOUR", "1"),
+        ];
+        let flags = Flags {
+            todofile: Some(PathBuf::from("flag-todo.txt")),
+            autoarchive: Some(true),
+            ..Flags::default()
+        };
+        let config = config(file, &env, flags);
+
+        assert_eq!(config.todofile().unwrap(), Path::new("flag-todo.txt"));
+        assert_eq!(config.confirm_above.value, 10);
+        assert_eq!(config.todofile.source, Source::Flag("--todofile"));
+        assert_eq!(config.donefile().unwrap(), Path::new("file-done.txt"));
+        assert_eq!(
+            config.donefile.source,
+            Source::File(PathBuf::from("config.toml"))
+        );
+        assert_eq!(
+            config.autoarchive,
+            Setting {
+                value: true,
+                source: Source::Flag("--auto-archive")
+            }
+        );
+        assert_eq!(
+            config.colour,
+            Setting {
+                value: false,
+                source: Source::Env("T_NO_COLOUR")
+            }
+        );
+        assert_eq!(config.date_format.value, "%d/%m");
+        assert_eq!(config.default_command.value, ViewCommand::NoDate);
+        assert_eq!(config.auto_id, Setting::new(true));
+    }
+
+    #[test]
+    fn can_choose_a_list() {
+        let file = r#"
+            [lists.team]
+            todofile = "team/todo.txt"
+            donefile = "team/done.txt"
+        "#;
+        let env = [("TODOFILE", "todo.txt"), ("DONEFILE", "done.txt")];
+        let flags = Flags {
+            list: Some("team".to_string()),
+            ..Flags::default()
+        };
+        let config = config(file, &env, flags);
+        assert_eq!(config.todofile().unwrap(), Path::new("team/todo.txt"));
+        assert_eq!(config.donefile.source, Source::Flag("--list"));
+        assert_eq!(
+            config.list_files("default").unwrap().todofile,
+            PathBuf::from("todo.txt")
+        );
+        assert_eq!(config.lists.len(), 2);
+
+        let mut other = Config::default();
+        assert!(other.choose_list(Some("nope".to_string())).is_err());
+    }
+
+    #[test]
+    fn finds_the_nearest_todo_file() {
+        let root = env::temp_dir().join(format!("t-find-test-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join("todo.txt"), "").unwrap();
+
+        let mut config = Config::default();
+        config.find_files(Some(&nested), Some(PathBuf::from("data")));
+        assert_eq!(config.todofile().unwrap(), root.join("todo.txt"));
+        assert_eq!(config.todofile.source, Source::Found);
+        assert_eq!(config.donefile().unwrap(), root.join("done.txt"));
+        assert_eq!(config.donefile.source, Source::NextToTodofile);
+
+        fs::remove_file(root.join("todo.txt")).unwrap();
+        let mut config = Config::default();
+        config.find_files(Some(&nested), Some(PathBuf::from("data")));
+        assert_eq!(config.todofile().unwrap(), Path::new("data/todo.txt"));
+        assert_eq!(config.donefile().unwrap(), Path::new("data/done.txt"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
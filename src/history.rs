@@ -0,0 +1,432 @@
+use super::{
+    config,
+    todo::Todo,
+    utility::{self, parse_todos, serialise},
+};
+
+use anyhow::anyhow;
+use std::fs;
+use std::path::PathBuf;
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+/// How many commands can be undone
+const MAX_ENTRIES: usize = 50;
+
+/// The lines of TODOFILE and DONEFILE that one command changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub time: String,
+    pub command: String,
+    todos: Change,
+    dones: Change,
+    /// The command also changed another list, which isn't journalled here
+    other_list_changed: bool,
+}
+
+/// Lines `before`, after the first `start` lines of a file and followed by
+/// `rest` more, that a command replaced with `after`
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Change {
+    start: usize,
+    rest: usize,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+impl Change {
+    fn between(before: &str, after: &str) -> Change {
+        let before: Vec<&str> = before.split('\n').collect();
+        let after: Vec<&str> = after.split('\n').collect();
+        let start = before
+            .iter()
+            .zip(&after)
+            .take_while(|(b, a)| b == a)
+            .count();
+        let rest = before[start..]
+            .iter()
+            .rev()
+            .zip(after[start..].iter().rev())
+            .take_while(|(b, a)| b == a)
+            .count();
+        let changed = |lines: &[&str]| -> Vec<String> {
+            lines[start..lines.len() - rest]
+                .iter()
+                .map(|l| l.to_string())
+                .collect()
+        };
+        Change {
+            start,
+            rest,
+            before: changed(&before),
+            after: changed(&after),
+        }
+    }
+
+    /// `text` with `from` at this change's place replaced by `to`, or `None`
+    /// if `text` doesn't have `from` there
+    fn apply(&self, text: &str, from: &[String], to: &[String]) -> Option<String> {
+        let lines: Vec<&str> = text.split('\n').collect();
+        let end = self.start + from.len();
+        if lines.len() != end + self.rest || lines[self.start..end] != *from {
+            return None;
+        }
+        let changed = to.iter().map(String::as_str);
+        let lines: Vec<&str> = lines[..self.start]
+            .iter()
+            .copied()
+            .chain(changed)
+            .chain(lines[end..].iter().copied())
+            .collect();
+        Some(lines.join("\n"))
+    }
+}
+
+/// A bounded journal of mutating commands, kept beside TODOFILE.
+///
+/// The last `undone` entries have been undone, and can be redone until another
+/// command is recorded.
+#[derive(Debug, Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+    undone: usize,
+}
+
+impl History {
+    pub fn load() -> Result<History> {
+        let path = utility::hidden_sibling(config::get().todofile()?, "history")?;
+        let mut history = match fs::read_to_string(&path) {
+            Ok(contents) => History::parse(&contents)?,
+            Err(_) => History::default(),
+        };
+        history.path = Some(path);
+        Ok(history)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        match &self.path {
+            Some(path) => utility::write_atomically(path, &self.format_for_save()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record(&mut self, command: &str, before: (&[Todo], &[Todo]), after: (&[Todo], &[Todo])) {
+        self.entries.truncate(self.entries.len() - self.undone);
+        self.undone = 0;
+        self.entries.push(Entry {
+            time: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            command: command.to_string(),
+            todos: Change::between(&serialise(before.0.iter()), &serialise(after.0.iter())),
+            dones: Change::between(&serialise(before.1.iter()), &serialise(after.1.iter())),
+            other_list_changed: false,
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    /// Refuse to undo the last recorded command, as undoing only this list's
+    /// side of it would leave the lists out of step
+    pub fn mark_other_list_changed(&mut self) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.other_list_changed = true;
+        }
+    }
+
+    pub fn undo_last(&mut self, todos: &mut Vec<Todo>, dones: &mut Vec<Todo>) -> Result<()> {
+        let i = self
+            .entries
+            .len()
+            .checked_sub(self.undone + 1)
+            .ok_or_else(|| anyhow!("Nothing to undo"))?;
+        let entry = &self.entries[i];
+        if entry.other_list_changed {
+            return Err(anyhow!(
+                "Can't undo `{}`, which changed another list too",
+                entry.command
+            )
+            .into());
+        }
+        let revert = |change: &Change, tasks: &[Todo]| {
+            change.apply(&serialise(tasks.iter()), &change.after, &change.before)
+        };
+        replace(
+            todos,
+            dones,
+            revert(&entry.todos, todos),
+            revert(&entry.dones, dones),
+        )?;
+        println!("UNDID: {}", entry.command);
+        self.undone += 1;
+        Ok(())
+    }
+
+    pub fn redo(&mut self, todos: &mut Vec<Todo>, dones: &mut Vec<Todo>) -> Result<()> {
+        if self.undone == 0 {
+            return Err(anyhow!("Nothing to redo").into());
+        }
+        let entry = &self.entries[self.entries.len() - self.undone];
+        let reapply = |change: &Change, tasks: &[Todo]| {
+            change.apply(&serialise(tasks.iter()), &change.before, &change.after)
+        };
+        replace(
+            todos,
+            dones,
+            reapply(&entry.todos, todos),
+            reapply(&entry.dones, dones),
+        )?;
+        println!("REDID: {}", entry.command);
+        self.undone -= 1;
+        Ok(())
+    }
+
+    pub fn print(&self) -> Result<()> {
+        let n = self.entries.len();
+        for (i, entry) in self.entries.iter().enumerate().rev() {
+            let status = if i >= n - self.undone {
+                " (undone)"
+            } else {
+                ""
+            };
+            let (added, removed) = line_changes(
+                &[&entry.todos.before[..], &entry.dones.before[..]].concat(),
+                &[&entry.todos.after[..], &entry.dones.after[..]].concat(),
+            );
+            println!(
+                "{:3} {} +{} -{} {}{}",
+                n - i,
+                entry.time,
+                added,
+                removed,
+                entry.command,
+                status
+            );
+        }
+        Ok(())
+    }
+
+    // Entries are a header line followed by a section for each file, with
+    // every changed line prefixed by `-` or `+` so that task text can't be
+    // mistaken for a header.
+    fn format_for_save(&self) -> String {
+        let mut out = format!("undone {}\n", self.undone);
+        for entry in &self.entries {
+            out.push_str(&format!("entry {}\t{}\n", entry.time, entry.command));
+            if entry.other_list_changed {
+                out.push_str("other list changed\n");
+            }
+            for (file, change) in [("todos", &entry.todos), ("dones", &entry.dones)] {
+                out.push_str(&format!(
+                    "change {} {} {}\n",
+                    file, change.start, change.rest
+                ));
+                for line in &change.before {
+                    out.push_str(&format!("-{}\n", line));
+                }
+                for line in &change.after {
+                    out.push_str(&format!("+{}\n", line));
+                }
+            }
+        }
+        out
+    }
+
+    fn parse(contents: &str) -> Result<History> {
+        let mut lines = contents.lines();
+        let undone = lines
+            .next()
+            .and_then(|l| l.strip_prefix("undone "))
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| anyhow!("Couldn't read undo history"))?;
+        let mut entries: Vec<Entry> = Vec::new();
+        // Older journals kept four whole snapshots per entry instead of changes
+        let mut snapshots: Vec<Vec<&str>> = Vec::new();
+        let mut dones = false;
+        for line in lines {
+            if let Some(header) = line.strip_prefix("entry ") {
+                finish_snapshots(entries.last_mut(), &mut snapshots)?;
+                let (time, command) = header.split_once('\t').unwrap_or((header, ""));
+                entries.push(Entry {
+                    time: time.to_string(),
+                    command: command.to_string(),
+                    todos: Change::default(),
+                    dones: Change::default(),
+                    other_list_changed: false,
+                });
+                dones = false;
+                continue;
+            }
+            let entry = match entries.last_mut() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let change = if dones {
+                &mut entry.dones
+            } else {
+                &mut entry.todos
+            };
+            if line == "other list changed" {
+                entry.other_list_changed = true;
+            } else if let Some(header) = line.strip_prefix("change ") {
+                let mut words = header.split(' ');
+                dones = words.next() == Some("dones");
+                let mut number = || {
+                    words
+                        .next()
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .ok_or_else(|| anyhow!("Couldn't read undo history"))
+                };
+                let (start, rest) = (number()?, number()?);
+                let change = if dones {
+                    &mut entry.dones
+                } else {
+                    &mut entry.todos
+                };
+                change.start = start;
+                change.rest = rest;
+            } else if let Some(text) = line.strip_prefix('-') {
+                change.before.push(text.to_string());
+            } else if let Some(text) = line.strip_prefix('+') {
+                change.after.push(text.to_string());
+            } else if line == "snapshot" {
+                snapshots.push(Vec::new());
+            } else if let (Some(text), Some(snapshot)) =
+                (line.strip_prefix('|'), snapshots.last_mut())
+            {
+                snapshot.push(text);
+            }
+        }
+        finish_snapshots(entries.last_mut(), &mut snapshots)?;
+        let undone = std::cmp::min(undone, entries.len());
+        Ok(History {
+            path: None,
+            entries,
+            undone,
+        })
+    }
+}
+
+/// Turn an old entry's whole-file snapshots into changes
+fn finish_snapshots(entry: Option<&mut Entry>, snapshots: &mut Vec<Vec<&str>>) -> Result<()> {
+    let entry = match entry {
+        Some(entry) if !snapshots.is_empty() => entry,
+        _ => return Ok(()),
+    };
+    let s: Vec<String> = snapshots.drain(..).map(|lines| lines.join("\n")).collect();
+    if s.len() != 4 {
+        return Err(anyhow!("Truncated undo history").into());
+    }
+    entry.todos = Change::between(&s[0], &s[2]);
+    entry.dones = Change::between(&s[1], &s[3]);
+    Ok(())
+}
+
+/// Replace the tasks with their reverted or redone text, refusing if either
+/// file no longer looks as the journal expects
+fn replace(
+    todos: &mut Vec<Todo>,
+    dones: &mut Vec<Todo>,
+    todos_text: Option<String>,
+    dones_text: Option<String>,
+) -> Result<()> {
+    match (todos_text, dones_text) {
+        (Some(todos_text), Some(dones_text)) => {
+            *todos = parse_todos(&todos_text);
+            *dones = parse_todos(&dones_text);
+            Ok(())
+        }
+        _ => Err(anyhow!("TODOFILE or DONEFILE has changed since then, so not reverting").into()),
+    }
+}
+
+/// Count lines added and removed between two sets of lines
+fn line_changes(before: &[String], after: &[String]) -> (usize, usize) {
+    let mut before: Vec<&String> = before.iter().filter(|l| !l.is_empty()).collect();
+    let mut added = 0;
+    for line in after.iter().filter(|l| !l.is_empty()) {
+        match before.iter().position(|b| *b == line) {
+            Some(pos) => {
+                before.remove(pos);
+            }
+            None => added += 1,
+        }
+    }
+    (added, before.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_undo_and_redo() {
+        let todos_before = parse_todos("first\nsecond");
+        let todos_after = parse_todos("second");
+        let dones_before = parse_todos("");
+        let dones_after = parse_todos("x 2021-09-13 first");
+
+        let mut history = History::default();
+        history.record(
+            "do 0",
+            (&todos_before, &dones_before),
+            (&todos_after, &dones_after),
+        );
+        let mut history = History::parse(&history.format_for_save()).unwrap();
+
+        let (mut todos, mut dones) = (todos_after.clone(), dones_after.clone());
+        history.undo_last(&mut todos, &mut dones).unwrap();
+        assert_eq!((&todos, &dones), (&todos_before, &dones_before));
+        assert!(history.undo_last(&mut todos, &mut dones).is_err());
+
+        history.redo(&mut todos, &mut dones).unwrap();
+        assert_eq!((&todos, &dones), (&todos_after, &dones_after));
+        assert!(history.redo(&mut todos, &mut dones).is_err());
+    }
+
+    #[test]
+    fn journals_only_changed_lines() {
+        let dones: Vec<Todo> = parse_todos(&"x 2021-09-01 old\n".repeat(100));
+        let mut dones_after = dones.clone();
+        dones_after.extend(parse_todos("x 2021-09-13 first"));
+        let mut history = History::default();
+        history.record(
+            "do 0",
+            (&parse_todos("first\nsecond"), &dones),
+            (&parse_todos("second"), &dones_after),
+        );
+        let saved = history.format_for_save();
+        assert!(!saved.contains("old"), "{}", saved);
+
+        // Older journals of whole snapshots can still be undone
+        let old = "undone 0\nentry 2021-09-13 10:00:00\tdo 0\n\
+                   snapshot\n|first\n|second\nsnapshot\n|\n\
+                   snapshot\n|second\nsnapshot\n|x 2021-09-13 first\n";
+        let mut history = History::parse(old).unwrap();
+        let (mut todos, mut dones) = (parse_todos("second"), parse_todos("x 2021-09-13 first"));
+        history.undo_last(&mut todos, &mut dones).unwrap();
+        assert_eq!(todos, parse_todos("first\nsecond"));
+        assert!(dones.is_empty());
+    }
+
+    #[test]
+    fn refuses_to_undo_over_other_changes() {
+        let before = parse_todos("first");
+        let after = parse_todos("first\nsecond");
+        let mut history = History::default();
+        history.record("add second", (&before, &[]), (&after, &[]));
+
+        let mut todos = parse_todos("first\nsecond\nthird");
+        assert!(history.undo_last(&mut todos, &mut vec![]).is_err());
+
+        let moved = parse_todos("second");
+        history.record("mv 0 team", (&after, &[]), (&moved, &[]));
+        history.mark_other_list_changed();
+        let mut history = History::parse(&history.format_for_save()).unwrap();
+        let mut todos = moved.clone();
+        assert!(history.undo_last(&mut todos, &mut vec![]).is_err());
+        assert_eq!(todos, moved);
+        let lines = |s: &str| -> Vec<String> { s.split('\n').map(String::from).collect() };
+        assert_eq!(line_changes(&lines("a\nb"), &lines("b\nc")), (1, 1));
+    }
+}
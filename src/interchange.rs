@@ -0,0 +1,500 @@
+use super::{
+    todo::{Record, Todo},
+    utility,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+/// Formats tasks can be exported to and imported from
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    /// An array of tasks, as `--format json` prints them
+    Json,
+    /// A `- [ ]` checklist
+    Markdown,
+    /// iCalendar VTODOs
+    Ical,
+}
+
+pub fn export<'a>(todos: impl Iterator<Item = &'a Todo>, format: Format) -> Result<String> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for todo in todos {
+                writer.serialize(CsvRow::from(Record::from(todo)))?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        Format::Json => {
+            let records: Vec<Record> = todos.map(Record::from).collect();
+            Ok(serde_json::to_string_pretty(&records)? + "\n")
+        }
+        Format::Markdown => Ok(todos.map(|t| markdown_item(t) + "\n").collect()),
+        Format::Ical => Ok(calendar(todos.map(vtodo))),
+    }
+}
+
+/// A calendar of the tasks that have a due date, as all-day to-dos or events.
+///
+/// Each task keeps its UID between runs, so calendars subscribed to the file
+/// update entries rather than duplicating them.
+pub fn due_calendar<'a>(todos: impl Iterator<Item = &'a Todo>, events: bool) -> String {
+    let due = todos.filter(|t| t.due_date.is_some());
+    if events {
+        calendar(due.filter_map(vevent))
+    } else {
+        calendar(due.map(vtodo))
+    }
+}
+
+/// Read tasks, numbered from `first_idx`
+pub fn import(contents: &str, format: Format, first_idx: usize) -> Result<Vec<Todo>> {
+    let mut todos = match format {
+        Format::Csv => csv::Reader::from_reader(contents.as_bytes())
+            .deserialize::<CsvRow>()
+            .map(|row| Ok(Todo::try_from(Record::try_from(row?)?)?))
+            .collect::<Result<Vec<_>>>()?,
+        Format::Json => serde_json::from_str::<Vec<Record>>(contents)?
+            .into_iter()
+            .map(Todo::try_from)
+            .collect::<std::result::Result<Vec<_>, _>>()?,
+        Format::Markdown => contents
+            .lines()
+            .filter_map(from_markdown_item)
+            .map(|line| line.parse())
+            .collect::<std::result::Result<Vec<_>, _>>()?,
+        Format::Ical => components(contents, "VTODO")
+            .into_iter()
+            .map(|props| from_vtodo(&props))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    for (i, todo) in todos.iter_mut().enumerate() {
+        todo.idx = first_idx + i;
+    }
+    Ok(todos)
+}
+
+// ------------------------------------------------------------
+//                              CSV
+// ------------------------------------------------------------
+
+/// One spreadsheet row per task, with lists space-separated
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRow {
+    done: bool,
+    priority: Option<char>,
+    text: String,
+    projects: String,
+    tags: String,
+    created: Option<String>,
+    due: Option<String>,
+    completed: Option<String>,
+    rec: Option<String>,
+    /// `key:value` pairs
+    extensions: String,
+}
+
+impl From<Record> for CsvRow {
+    fn from(record: Record) -> CsvRow {
+        CsvRow {
+            done: record.done,
+            priority: record.priority,
+            text: record.text,
+            projects: record.projects.join(" "),
+            tags: record.tags.join(" "),
+            created: record.created,
+            due: record.due,
+            completed: record.completed,
+            rec: record.rec,
+            extensions: record
+                .extensions
+                .iter()
+                .map(|(k, v)| format!("{}:{}", k, v))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+impl TryFrom<CsvRow> for Record {
+    type Error = anyhow::Error;
+    fn try_from(row: CsvRow) -> std::result::Result<Record, Self::Error> {
+        let words = |s: &str| s.split_whitespace().map(String::from).collect();
+        let extensions = row
+            .extensions
+            .split_whitespace()
+            .map(|pair| {
+                let (k, v) = pair
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Expected `key:value`, got `{}`", pair))?;
+                Ok((k.to_string(), v.to_string()))
+            })
+            .collect::<std::result::Result<_, Self::Error>>()?;
+        Ok(Record {
+            idx: 0,
+            done: row.done,
+            priority: row.priority,
+            text: row.text,
+            projects: words(&row.projects),
+            tags: words(&row.tags),
+            created: row.created,
+            due: row.due,
+            completed: row.completed,
+            rec: row.rec,
+            extensions,
+        })
+    }
+}
+
+// ------------------------------------------------------------
+//                            Markdown
+// ------------------------------------------------------------
+
+// A done task keeps its completion date after the checkbox, as `x` would
+fn markdown_item(todo: &Todo) -> String {
+    let line = todo.format_for_save();
+    match line.strip_prefix("x ") {
+        Some(rest) => format!("- [x] {}", rest),
+        None => format!("- [ ] {}", line),
+    }
+}
+
+fn from_markdown_item(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    let item = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))?;
+    if let Some(text) = item.strip_prefix("[ ] ") {
+        return Some(text.to_string());
+    }
+    let text = item
+        .strip_prefix("[x] ")
+        .or_else(|| item.strip_prefix("[X] "))?;
+    let first = text.split_whitespace().next().unwrap_or_default();
+    Some(match utility::parse_ymd(first) {
+        Some(_) => format!("x {}", text),
+        None => format!("x {} {}", utility::date_today().format("%Y-%m-%d"), text),
+    })
+}
+
+// ------------------------------------------------------------
+//                           iCalendar
+// ------------------------------------------------------------
+
+/// A VCALENDAR holding `components`, each a list of property lines
+fn calendar(components: impl Iterator<Item = Vec<String>>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//t//todo.txt//EN".to_string(),
+    ];
+    lines.extend(components.flatten());
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|l| fold(l) + "\r\n").collect()
+}
+
+/// The same for every export of a task: from its `id:` if it has one,
+/// otherwise from its text and creation date.
+fn uid(todo: &Todo) -> String {
+    match todo.extension("id") {
+        Some(id) => format!("id-{}@t", id),
+        None => {
+            let key = format!(
+                "{} {}",
+                todo.created_date.as_deref().unwrap_or(""),
+                todo.task
+            );
+            format!("{:016x}@t", fnv1a(key.as_bytes()))
+        }
+    }
+}
+
+// A fixed hash, unlike std's, so UIDs don't change between builds
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// `20210913` from `2021-09-13`
+fn ical_date(ymd: &str) -> String {
+    ymd.replace('-', "")
+}
+
+/// Properties that every component describing a task shares
+fn common_properties(todo: &Todo) -> Vec<String> {
+    let stamp = todo
+        .created_date
+        .clone()
+        .unwrap_or_else(|| utility::date_today().format("%Y-%m-%d").to_string());
+    let mut props = vec![
+        format!("UID:{}", uid(todo)),
+        format!("DTSTAMP:{}T000000Z", ical_date(&stamp)),
+        format!("SUMMARY:{}", escape(&todo.task)),
+    ];
+    let categories: Vec<String> = todo
+        .projects
+        .iter()
+        .chain(&todo.tags)
+        .map(|c| escape(c))
+        .collect();
+    if !categories.is_empty() {
+        props.push(format!("CATEGORIES:{}", categories.join(",")));
+    }
+    // 1 is highest; there are only nine levels, so J to Z share the lowest
+    if let Some(letter) = todo.pri.letter() {
+        let level = std::cmp::min(letter as u8 - b'A' + 1, 9);
+        props.push(format!("PRIORITY:{}", level));
+    }
+    props
+}
+
+fn vtodo(todo: &Todo) -> Vec<String> {
+    let mut props = vec!["BEGIN:VTODO".to_string()];
+    props.extend(common_properties(todo));
+    if let Some(created) = &todo.created_date {
+        props.push(format!("CREATED:{}T000000Z", ical_date(created)));
+    }
+    if let Some(due) = &todo.due_date {
+        props.push(format!("DUE;VALUE=DATE:{}", ical_date(due)));
+    }
+    match &todo.done_date {
+        Some(done) => {
+            props.push("STATUS:COMPLETED".to_string());
+            props.push(format!("COMPLETED:{}T000000Z", ical_date(done)));
+        }
+        None => props.push("STATUS:NEEDS-ACTION".to_string()),
+    }
+    // The whole line, so that nothing is lost coming back to t
+    props.push(format!("X-TODOTXT:{}", escape(&todo.format_for_save())));
+    props.push("END:VTODO".to_string());
+    props
+}
+
+fn vevent(todo: &Todo) -> Option<Vec<String>> {
+    let due = utility::parse_ymd(todo.due_date.as_ref()?)?;
+    let mut props = vec!["BEGIN:VEVENT".to_string()];
+    props.extend(common_properties(todo));
+    props.push(format!("DTSTART;VALUE=DATE:{}", due.format("%Y%m%d")));
+    props.push(format!(
+        "DTEND;VALUE=DATE:{}",
+        (due + chrono::Duration::days(1)).format("%Y%m%d")
+    ));
+    // Don't show as busy all day
+    props.push("TRANSP:TRANSPARENT".to_string());
+    props.push("END:VEVENT".to_string());
+    Some(props)
+}
+
+fn from_vtodo(props: &[(String, String)]) -> Result<Todo> {
+    let get = |name: &str| {
+        props
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| unescape(v))
+    };
+    if let Some(line) = get("X-TODOTXT") {
+        return Ok(line.parse()?);
+    }
+    let date = |name: &str| -> Result<Option<String>> {
+        match get(name) {
+            Some(value) => from_ical_date(&value)
+                .map(Some)
+                .ok_or_else(|| anyhow!("Couldn't read {} date `{}`", name, value).into()),
+            None => Ok(None),
+        }
+    };
+    let mut record = Record {
+        text: get("SUMMARY").unwrap_or_default(),
+        created: date("CREATED")?,
+        due: date("DUE")?,
+        completed: date("COMPLETED")?,
+        ..Record::default()
+    };
+    record.done = record.completed.is_some() || get("STATUS").as_deref() == Some("COMPLETED");
+    record.priority = match get("PRIORITY").and_then(|p| p.parse::<u8>().ok()) {
+        Some(level @ 1..=9) => Some((b'A' + level - 1) as char),
+        _ => None,
+    };
+    let categories = props
+        .iter()
+        .find(|(n, _)| n == "CATEGORIES")
+        .map(|(_, v)| split_escaped(v, ','))
+        .unwrap_or_default();
+    for category in categories.into_iter().filter(|c| !c.is_empty()) {
+        if category.starts_with('+') {
+            record.projects.push(category);
+        } else {
+            record.tags.push(category);
+        }
+    }
+    Ok(Todo::try_from(record)?)
+}
+
+/// The properties of each `kind` of component, unfolded, with parameters dropped
+fn components(contents: &str, kind: &str) -> Vec<Vec<(String, String)>> {
+    let mut unfolded: Vec<String> = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim_end_matches('\r');
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            unfolded.last_mut(),
+        ) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => unfolded.push(line.to_string()),
+        }
+    }
+    let mut components = Vec::new();
+    let mut current: Option<Vec<(String, String)>> = None;
+    for line in unfolded {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name, value),
+            None => continue,
+        };
+        let name = name
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        match (name.as_str(), &mut current) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case(kind) => current = Some(Vec::new()),
+            ("END", Some(_)) if value.eq_ignore_ascii_case(kind) => {
+                components.extend(current.take());
+            }
+            (_, Some(props)) => props.push((name, value.to_string())),
+            _ => {}
+        }
+    }
+    components
+}
+
+// `20210913` or `20210913T120000Z` to `2021-09-13`
+fn from_ical_date(value: &str) -> Option<String> {
+    let digits = value
+        .get(..8)
+        .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))?;
+    let ymd = format!("{}-{}-{}", &digits[..4], &digits[4..6], &digits[6..]);
+    utility::parse_ymd(&ymd).map(|_| ymd)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    split_escaped(text, '\0').concat()
+}
+
+// Unescape `text`, splitting it wherever `sep` isn't escaped
+fn split_escaped(text: &str, sep: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => part.push('\n'),
+                Some(other) => part.push(other),
+                None => {}
+            },
+            c if c == sep => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    parts
+}
+
+// Lines are at most 75 octets, continued on lines starting with a space
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            len = 1;
+        }
+        folded.push(c);
+        len += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASKS: &str = "(A) 2021-09-01 call bob, about it due:2021-09-20 rec:+1w id:7 +work @phone
+x 2021-09-10 2021-09-01 write report +work.clientA
+(K) tidy desk; then the shelf est:2 id:9 @home";
+
+    fn round_trip(format: Format) -> Vec<Todo> {
+        let todos = utility::parse_todos(TASKS);
+        let exported = export(todos.iter(), format).unwrap();
+        import(&exported, format, 0).unwrap()
+    }
+
+    #[test]
+    fn can_round_trip_every_format() {
+        let todos = utility::parse_todos(TASKS);
+        for format in [Format::Csv, Format::Json, Format::Markdown, Format::Ical] {
+            assert_eq!(round_trip(format), todos, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn can_read_other_calendars() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:abc\r\nSUMMARY:call bob\\,\r\n  urgently\r\n\
+                   DUE;VALUE=DATE:20210920\r\nPRIORITY:2\r\nCATEGORIES:+work,phone\r\n\
+                   STATUS:COMPLETED\r\nCOMPLETED:20210913T101500Z\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let todos = import(ics, Format::Ical, 3).unwrap();
+        assert_eq!(
+            todos[0].format_for_save(),
+            "x 2021-09-13 call bob, urgently due:2021-09-20 +work @phone"
+        );
+        assert_eq!(todos[0].idx, 3);
+
+        let vtodo = vtodo(&utility::parse_todos(TASKS)[2]).join("\n");
+        assert!(vtodo.contains("PRIORITY:9"));
+        assert!(vtodo.contains("SUMMARY:tidy desk\\; then the shelf"));
+        assert!(vtodo.contains("UID:id-9@t"));
+
+        let bad = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nSUMMARY:x\r\nDUE:a€€x\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        assert!(import(bad, Format::Ical, 0).is_err());
+    }
+
+    #[test]
+    fn due_calendar_has_dated_tasks_with_stable_uids() {
+        let todos = utility::parse_todos(TASKS);
+        let events = due_calendar(todos.iter(), true);
+        assert_eq!(events.matches("BEGIN:VEVENT").count(), 1);
+        assert!(events.contains("DTSTART;VALUE=DATE:20210920\r\nDTEND;VALUE=DATE:20210921\r\n"));
+        assert!(events.contains("UID:id-7@t"));
+
+        let mut moved = todos.clone();
+        moved[0].schedule("2021-10-01").unwrap();
+        assert!(due_calendar(moved.iter(), false).contains("UID:id-7@t"));
+    }
+
+    #[test]
+    fn can_read_markdown_checklists() {
+        let md = "# Plan\n- [ ] draft +docs\n  * [x] outline\nnot a task\n- [X] 2021-09-01 review";
+        let lines: Vec<String> = import(md, Format::Markdown, 0)
+            .unwrap()
+            .iter()
+            .map(|t| t.format_for_save())
+            .collect();
+        assert_eq!(
+            lines,
+            vec!["draft +docs", "x 2021-09-13 outline", "x 2021-09-01 review"]
+        );
+        assert!(fold(&"a".repeat(100)).starts_with(&format!("{}\r\n a", "a".repeat(75))));
+    }
+}
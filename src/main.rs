@@ -2,21 +2,99 @@ extern crate anyhow;
 extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
-use clap::{Parser, Subcommand};
+use anyhow::anyhow;
+use clap::{Args, Parser, Subcommand};
+use config::ViewCommand;
+use taskref::TaskRef;
+use todo::Todo;
+use utility::SortOrder;
 
 mod colour;
+mod config;
+mod history;
+mod interchange;
 mod modify;
+mod pick;
+mod query;
+mod taskref;
 mod todo;
+mod tui;
 mod utility;
 mod view;
 
 #[derive(Debug, Parser)]
 #[command(name = "t", about = "kinda like todo.sh")]
 struct Cli {
+    /// What to do; without one, runs `default_command` from the config file
     #[command(subcommand)]
-    command: Command,
-    #[arg(short, long)]
+    command: Option<Command>,
+    #[arg(short, long, global = true)]
     auto_archive: bool,
+    #[arg(long, global = true, conflicts_with = "auto_archive")]
+    no_auto_archive: bool,
+    /// Include tasks whose threshold (t:) date is in the future
+    #[arg(long, global = true)]
+    all: bool,
+    /// Use this todo file, instead of TODOFILE or the config file's
+    #[arg(long, global = true)]
+    todofile: Option<std::path::PathBuf>,
+    /// Use this done file, instead of DONEFILE or the config file's
+    #[arg(long, global = true)]
+    donefile: Option<std::path::PathBuf>,
+    /// Use a list from the config file, instead of the default todo and done files
+    #[arg(short, long, global = true, conflicts_with_all = ["todofile", "donefile"])]
+    list: Option<String>,
+    /// Run a view over every list from the config file
+    #[arg(short = 'L', long, global = true)]
+    all_lists: bool,
+    #[arg(long, global = true)]
+    no_colour: bool,
+    /// Choose the tasks to act on with a fuzzy finder, instead of by index
+    #[arg(short = 'i', long, global = true)]
+    pick: bool,
+    /// Print views as text, a JSON array, or JSON lines
+    #[arg(long, global = true, value_enum)]
+    format: Option<config::OutputFormat>,
+    /// How to show dates, e.g. "%d %b"
+    #[arg(long, global = true)]
+    date_format: Option<String>,
+}
+
+/// Acting on every task that matches a filter, instead of on given tasks
+#[derive(Args, Debug, Clone)]
+struct Bulk {
+    /// Every task matching this filter, e.g. "+sprint12 and -@waiting"
+    #[arg(
+        long = "where",
+        value_name = "FILTER",
+        allow_hyphen_values = true,
+        conflicts_with = "idxs"
+    )]
+    filters: Vec<String>,
+    /// Only show the tasks that would change
+    #[arg(short = 'n', long, requires = "filters")]
+    dry_run: bool,
+    /// Don't ask before changing more than `confirm_above` tasks
+    #[arg(short, long, requires = "filters")]
+    yes: bool,
+}
+
+impl Cli {
+    fn flags(&self) -> config::Flags {
+        config::Flags {
+            todofile: self.todofile.clone(),
+            donefile: self.donefile.clone(),
+            autoarchive: match (self.auto_archive, self.no_auto_archive) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            colour: self.no_colour.then_some(false),
+            date_format: self.date_format.clone(),
+            format: self.format,
+            list: self.list.clone(),
+        }
+    }
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -30,8 +108,12 @@ enum Command {
     Add { text: String },
     /// Add a task and complete immediately
     Addx { text: String },
-    /// Add a task and prioritise as 'A'
-    Adda { text: String },
+    /// Add a task and prioritise as 'A', or the given priority
+    Adda {
+        text: String,
+        #[arg(short, long, default_value = "A")]
+        priority: String,
+    },
     /// Add a task and schedule today
     Addt { text: String },
 
@@ -39,64 +121,170 @@ enum Command {
     //                     Modify existing tasks
     // ------------------------------------------------------------
     /// Append text to a task
-    #[command(visible_aliases = &["app"])]
-    Append { idx: usize, text: String },
+    #[command(visible_aliases = &["app"], allow_missing_positional = true)]
+    Append {
+        #[arg(required_unless_present = "pick", conflicts_with = "pick")]
+        idx: Option<TaskRef>,
+        text: String,
+    },
     /// Prepend text to a task
-    #[command(visible_aliases = &["pre"])]
-    Prepend { idx: usize, text: String },
+    #[command(visible_aliases = &["pre"], allow_missing_positional = true)]
+    Prepend {
+        #[arg(required_unless_present = "pick", conflicts_with = "pick")]
+        idx: Option<TaskRef>,
+        text: String,
+    },
+    /// Replace a task's text, keeping its priority, dates and metadata
+    #[command(allow_missing_positional = true)]
+    Replace {
+        #[arg(required_unless_present = "pick", conflicts_with = "pick")]
+        idx: Option<TaskRef>,
+        text: String,
+    },
+    /// Substitute REPLACEMENT for a regex in each task matching the filters
+    Subst {
+        pattern: String,
+        /// May refer to groups in PATTERN, like $1
+        replacement: String,
+        /// Only show the changes
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        /// Don't ask before changing tasks
+        #[arg(short, long)]
+        yes: bool,
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
+    /// Change a task in $EDITOR, or every task without IDX
+    Edit { idx: Option<TaskRef> },
     /// Prioritise a task
-    #[command(visible_aliases = &["pri", "p"])]
-    Prioritise { idx: usize, priority: String },
+    #[command(visible_aliases = &["pri", "p"], allow_missing_positional = true)]
+    Prioritise {
+        #[arg(required_unless_present = "pick", conflicts_with = "pick")]
+        idx: Option<TaskRef>,
+        priority: String,
+    },
     /// Deprioritise a task
     #[command(visible_aliases = &["dp"])]
-    Deprioritise { idx: usize },
+    Deprioritise {
+        #[arg(required_unless_present = "pick", conflicts_with = "pick")]
+        idx: Option<TaskRef>,
+    },
+    /// Raise priority by one letter (none becomes Z)
+    #[command(visible_aliases = &["pri-up"])]
+    PriUp { idxs: Vec<TaskRef> },
+    /// Lower priority by one letter (Z becomes none)
+    #[command(visible_aliases = &["pri-down"])]
+    PriDown { idxs: Vec<TaskRef> },
 
     // ------------------------------------------------------------
     //                 Modify completion / existance
     // ------------------------------------------------------------
     /// Remove a task
     #[command(visible_aliases = &["rm", "del"])]
-    Remove { idxs: Vec<usize> },
+    Remove {
+        idxs: Vec<TaskRef>,
+        #[command(flatten)]
+        bulk: Bulk,
+    },
+    /// Move a task to another list from the config file
+    #[command(visible_aliases = &["mv"], allow_missing_positional = true)]
+    Move {
+        #[arg(required_unless_present = "pick", conflicts_with = "pick")]
+        idx: Option<TaskRef>,
+        #[arg(value_name = "LIST")]
+        dest: String,
+    },
     /// Move task to DONEFILE
-    Do { idxs: Vec<usize> },
+    Do {
+        idxs: Vec<TaskRef>,
+        #[command(flatten)]
+        bulk: Bulk,
+    },
     /// Move task from DONEFILE to TODOFILE
-    Undo { idxs: Vec<usize> },
+    Undo { idxs: Vec<TaskRef> },
     /// Move done tasks into DONEFILE
     Archive,
 
+    // ------------------------------------------------------------
+    //                           History
+    // ------------------------------------------------------------
+    /// Revert the last command that changed TODOFILE or DONEFILE
+    #[command(visible_aliases = &["undo-last"])]
+    UndoLast,
+    /// Re-apply the last undone command
+    Redo,
+    /// View commands that can be undone
+    History,
+
     // ------------------------------------------------------------
     //                          Scheduling
     // ------------------------------------------------------------
-    /// Schedule a task
-    #[command(visible_aliases = &["s"])]
-    Schedule { idx: usize, date: String },
+    /// Schedule a task, on a date like 2021-09-20, friday, +3d, next month or jan 15
+    #[command(visible_aliases = &["s"], allow_missing_positional = true)]
+    Schedule {
+        #[arg(required_unless_present = "pick", conflicts_with = "pick")]
+        idx: Option<TaskRef>,
+        #[arg(required = true)]
+        date: Vec<String>,
+    },
     /// Remove due date from task
-    Unschedule { idxs: Vec<usize> },
+    Unschedule {
+        idxs: Vec<TaskRef>,
+        #[command(flatten)]
+        bulk: Bulk,
+    },
     /// Schedule task today
-    Today { idxs: Vec<usize> },
+    Today {
+        idxs: Vec<TaskRef>,
+        #[command(flatten)]
+        bulk: Bulk,
+    },
 
     // ------------------------------------------------------------
     //                             Views
     // ------------------------------------------------------------
     /// View tasks
     #[command(visible_aliases = &["ls"])]
-    List { filters: Vec<String> },
+    List {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
     /// View tasks with a priority
     #[command(visible_aliases = &["lsp"])]
-    ListPriority { filters: Vec<String> },
+    ListPriority {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
     /// View done tasks
     #[command(visible_aliases = &["lsd"])]
-    ListDone { filters: Vec<String> },
+    ListDone {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
     /// View scheduled tasks
     Due {
         n_days: Option<usize>,
+        #[arg(allow_hyphen_values = true)]
         filters: Vec<String>,
     },
     /// View unscheduled tasks
-    NoDate { filters: Vec<String> },
+    NoDate {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
+    /// View tasks hidden until a future threshold (t:) date
+    Upcoming {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
     /// View done tasks, by date, for last N days
     #[command(visible_aliases = &["ds"])]
-    DoneSummary { days: i64, filters: Vec<String> },
+    DoneSummary {
+        days: i64,
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
 
     // ------------------------------------------------------------
     //                       Views - Projects
@@ -109,7 +297,10 @@ enum Command {
     Projectless,
     /// View tasks grouped by project
     #[command(visible_aliases = &["pv"])]
-    ProjectView { filters: Vec<String> },
+    ProjectView {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
 
     // ------------------------------------------------------------
     //                         Views - Tags
@@ -122,32 +313,313 @@ enum Command {
     Tagless,
     /// View tasks grouped by context
     #[command(visible_aliases = &["tv"])]
-    TagView { filters: Vec<String> },
+    TagView {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
 
     // ------------------------------------------------------------
     //                            Utility
     // ------------------------------------------------------------
+    /// Run a view saved in the config file, or list the saved views
+    #[command(visible_aliases = &["v"])]
+    View {
+        name: Option<String>,
+        /// Added to the view's own filters
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
+    /// Write tasks in another format, to stdout or a file
+    Export {
+        // Not `format`, which is taken by the global `--format`
+        #[arg(value_name = "FORMAT")]
+        to: interchange::Format,
+        /// Export DONEFILE instead of TODOFILE
+        #[arg(long)]
+        done: bool,
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
+    /// Add tasks from a file in another format, or `-` for stdin
+    Import {
+        #[arg(value_name = "FORMAT")]
+        from: interchange::Format,
+        file: std::path::PathBuf,
+    },
+    /// Browse and change tasks interactively
+    Tui {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
+    /// Write an iCalendar file of tasks with a due date, to stdout or a file
+    #[command(visible_aliases = &["ics"])]
+    Calendar {
+        /// All-day events instead of to-dos, for calendars that don't show to-dos
+        #[arg(long)]
+        events: bool,
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
+    /// Show each setting and where it came from
+    Config,
+    /// Create empty todo and done files, in DIR or wherever they're configured
+    Init { dir: Option<std::path::PathBuf> },
     /// Open link in task
     #[command(visible_aliases = &["open", "url"])]
-    Link { indices: Vec<usize> },
+    Link { indices: Vec<TaskRef> },
     #[command(visible_aliases = &["urls"])]
-    Links { filters: Vec<String> },
+    Links {
+        #[arg(allow_hyphen_values = true)]
+        filters: Vec<String>,
+    },
+}
+
+impl Command {
+    /// Commands that never change tasks
+    fn only_shows(&self) -> bool {
+        self.lists_todos()
+            || matches!(
+                self,
+                Command::ListDone { .. }
+                    | Command::DoneSummary { .. }
+                    | Command::Projects
+                    | Command::Tags
+            )
+    }
+
+    /// What to run when no command is given
+    fn default_view(config: &config::Config) -> Command {
+        let filters = config.default_filters.value.clone();
+        match config.default_command.value {
+            ViewCommand::List => Command::List { filters },
+            ViewCommand::ListPriority => Command::ListPriority { filters },
+            ViewCommand::ListDone => Command::ListDone { filters },
+            ViewCommand::Due => Command::Due {
+                n_days: None,
+                filters,
+            },
+            ViewCommand::NoDate => Command::NoDate { filters },
+            ViewCommand::Upcoming => Command::Upcoming { filters },
+            ViewCommand::ProjectView => Command::ProjectView { filters },
+            ViewCommand::TagView => Command::TagView { filters },
+            ViewCommand::Links => Command::Links { filters },
+        }
+    }
+
+    /// Views that show TODOFILE indices, which later commands may refer to
+    fn lists_todos(&self) -> bool {
+        matches!(
+            self,
+            Command::List { .. }
+                | Command::ListPriority { .. }
+                | Command::Due { .. }
+                | Command::NoDate { .. }
+                | Command::Upcoming { .. }
+                | Command::Projectless
+                | Command::ProjectView { .. }
+                | Command::Tagless
+                | Command::TagView { .. }
+                | Command::Links { .. }
+                | Command::View { name: Some(_), .. }
+        )
+    }
+}
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+/// Run a command that only shows tasks
+fn show(command: &Command, todos: &[Todo], dones: &[Todo], show_all: bool) -> Result<()> {
+    match command {
+        // ------------------------------------------------------------
+        //                             Views
+        // ------------------------------------------------------------
+        Command::List { filters } => view::list(
+            utility::actionable(todos.iter(), show_all),
+            filters,
+            SortOrder::Priority,
+        ),
+        Command::ListPriority { filters } => view::list_priority(
+            utility::actionable(todos.iter(), show_all),
+            filters,
+            SortOrder::Priority,
+        ),
+        Command::ListDone { filters } => view::done(dones.iter(), filters, SortOrder::Priority),
+        Command::Due { n_days, filters } => view::due(todos.iter(), n_days.unwrap_or(0), filters),
+        Command::NoDate { filters } => view::no_date(
+            utility::actionable(todos.iter(), show_all),
+            filters,
+            SortOrder::Priority,
+        ),
+        Command::Upcoming { filters } => view::upcoming(todos.iter(), filters),
+        Command::DoneSummary { days, filters } => view::done_summary(dones.iter(), filters, *days),
+        Command::View {
+            name: Some(name),
+            filters,
+        } => config::get()
+            .view(name)
+            .and_then(|saved| view::saved(saved, filters, todos, dones, show_all)),
+
+        // ------------------------------------------------------------
+        //                       Views - Projects
+        // ------------------------------------------------------------
+        Command::Projects => view::projects(todos.iter()),
+        Command::Projectless => view::no_projects(todos.iter()),
+        Command::ProjectView { filters } => {
+            view::grouped_by_project(utility::actionable(todos.iter(), show_all), filters)
+        }
+
+        // ------------------------------------------------------------
+        //                         Views - Tags
+        // ------------------------------------------------------------
+        Command::Tags => view::tags(todos.iter()),
+        Command::Tagless => view::no_tags(todos.iter()),
+        Command::TagView { filters } => {
+            view::grouped_by_tag(utility::actionable(todos.iter(), show_all), filters)
+        }
+        Command::Links { filters } => view::links(todos.iter(), filters),
+
+        _ => Err(anyhow!("Not a view").into()),
+    }
 }
 
-type Result<T> = ::std::result::Result<T, Box<dyn (::std::error::Error)>>;
+fn init(config: &config::Config, dir: Option<&std::path::Path>) -> Result<()> {
+    let (todofile, donefile) = match dir {
+        Some(dir) => (dir.join("todo.txt"), dir.join("done.txt")),
+        None => (
+            config.todofile()?.to_path_buf(),
+            config.donefile()?.to_path_buf(),
+        ),
+    };
+    for path in [todofile, donefile] {
+        if utility::create_if_missing(&path)? {
+            println!("Created {}", path.display());
+        } else {
+            println!("{} already exists", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// The tasks not yet done that match `--where`, listed first if this is a dry
+/// run or there are enough of them to ask before going ahead
+fn bulk_indices(bulk: &Bulk, todos: &[Todo], verb: &str, show_all: bool) -> Result<Vec<usize>> {
+    let matching: Vec<&Todo> =
+        utility::actionable(utility::todo_filter(todos.iter(), &bulk.filters)?, show_all)
+            .filter(|t| t.done_date.is_none())
+            .collect();
+    if matching.is_empty() {
+        println!("No tasks match");
+        return Ok(Vec::new());
+    }
+    let ask = matching.len() > config::get().confirm_above.value && !bulk.yes;
+    if bulk.dry_run || ask {
+        matching.iter().for_each(|t| println!("{}", t));
+    }
+    if bulk.dry_run {
+        println!("{} tasks to {}; nothing changed", matching.len(), verb);
+        return Ok(Vec::new());
+    }
+    if ask && !utility::confirm(&format!("{} tasks to {}. Go ahead?", matching.len(), verb))? {
+        println!("Nothing changed");
+        return Ok(Vec::new());
+    }
+    Ok(matching.iter().map(|t| t.idx).collect())
+}
+
+/// Another list's tasks after a task was moved there, kept locked until saved
+struct MovedTo {
+    todofile: std::path::PathBuf,
+    todos: Vec<Todo>,
+    state: utility::FileState,
+    _lock: std::fs::File,
+}
+
+/// Move a task to the list called `dest`, leaving both lists to be checked
+/// and saved together.
+///
+/// This list is already locked, so rather than wait for the other, which could
+/// deadlock with a move the opposite way, give up if it's in use.
+fn move_to_list(idx: usize, dest: &str, todos: &mut Vec<Todo>) -> Result<MovedTo> {
+    let config = config::get();
+    let target = config.list_files(dest)?;
+    if target.todofile == config.todofile()? {
+        return Err(anyhow!("Task is already in list `{}`", dest).into());
+    }
+    let lock = utility::try_lock_todo_file(&target.todofile)?;
+    let (mut target_todos, state) = utility::parse_file(&target.todofile)?;
+    modify::move_task(idx, todos, &mut target_todos)?;
+    Ok(MovedTo {
+        todofile: target.todofile.clone(),
+        todos: target_todos,
+        state,
+        _lock: lock,
+    })
+}
+
+/// Run a view over every configured list in turn, under each list's name
+fn show_all_lists(command: &Command, show_all: bool) -> Result<()> {
+    if !command.only_shows() {
+        return Err(anyhow!("Only views can be run across all lists").into());
+    }
+    if config::get().format.value != config::OutputFormat::Text {
+        return Err(anyhow!("--format only works with one list at a time; use --list").into());
+    }
+    for (name, files) in &config::get().lists {
+        let (todos, _) = utility::parse_file(&files.todofile)?;
+        let (dones, _) = utility::parse_file(&files.donefile)?;
+        println!("===== {} {}", name, "=".repeat(73 - name.len().min(73)));
+        show(command, &todos, &dones, show_all)?;
+        println!();
+    }
+    Ok(())
+}
 
 fn main() -> Result<()> {
     pretty_env_logger::init_timed();
 
-    let t_dont_autoarchive_env =
-        std::env::var("T_DONT_AUTOARCHIVE").unwrap_or_else(|_| "false".to_string());
-
     let opts = Cli::parse();
 
-    let mut autoarchive =
-        opts.auto_archive || t_dont_autoarchive_env.is_empty() || t_dont_autoarchive_env == "false";
+    match config::Config::load(opts.flags()) {
+        Ok(config) => config::init(config),
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let config = config::get();
+    let command = opts
+        .command
+        .unwrap_or_else(|| Command::default_view(config));
+    // Works without any todo file, to help find out why there isn't one
+    if let Command::Config = command {
+        return config.print();
+    }
+    if let Command::Init { dir } = &command {
+        return init(config, dir.as_deref());
+    }
+    if opts.all_lists {
+        if let Err(e) = show_all_lists(&command, opts.all) {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut autoarchive = config.autoarchive.value;
+
+    let _lock = match config.todofile().and_then(utility::lock_todo_file) {
+        Ok(lock) => lock,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let mut todos = match utility::get_todos() {
+    let (mut todos, todo_state) = match utility::get_todos() {
         Ok(todos) => todos,
         Err(e) => {
             println!("{}", e);
@@ -155,7 +627,7 @@ fn main() -> Result<()> {
         }
     };
 
-    let mut dones = match utility::get_dones() {
+    let (mut dones, done_state) = match utility::get_dones() {
         Ok(dones) => dones,
         Err(e) => {
             println!("{}", e);
@@ -163,6 +635,8 @@ fn main() -> Result<()> {
         }
     };
 
+    let todos_at_start = todos.clone();
+    let dones_at_start = dones.clone();
     let num_todos_at_start = todos.len();
     let num_done_at_start = dones.len();
 
@@ -172,78 +646,212 @@ fn main() -> Result<()> {
     // let (command, mut autoarchive) = parse_args(num_todos_at_start, num_done_at_start)?;
     debug!("Autoarchiving? {}", autoarchive);
 
-    let result = match opts.command {
+    let show_all = opts.all;
+    let mut listed = match taskref::Listed::load() {
+        Ok(listed) => listed,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let lists_todos = command.lists_todos();
+    let pick = opts.pick;
+    // The fuzzy finder offers the tasks not yet done that match `filters`
+    let pick_from = |filters: &[String]| -> Result<TaskRef> {
+        let matching = utility::actionable(utility::todo_filter(todos.iter(), filters)?, show_all);
+        Ok(TaskRef::Pick(
+            matching
+                .filter(|t| t.done_date.is_none())
+                .map(|t| t.idx)
+                .collect(),
+        ))
+    };
+    let resolve = |refs: &[TaskRef]| -> Result<Vec<usize>> {
+        let mut refs = refs.to_vec();
+        if pick {
+            refs.push(pick_from(&[])?);
+        }
+        taskref::resolve(&refs, &todos, Some(&listed))
+    };
+    let resolve_one = |r: &Option<TaskRef>| match r {
+        Some(r) => taskref::resolve_one(r, &todos, Some(&listed)),
+        None => taskref::resolve_one(&pick_from(&[])?, &todos, None),
+    };
+    let targets = |idxs: &[TaskRef], bulk: &Bulk, verb: &str| {
+        if bulk.filters.is_empty() {
+            resolve(idxs)
+        } else if pick {
+            taskref::resolve(&[pick_from(&bulk.filters)?], &todos, None)
+        } else {
+            bulk_indices(bulk, &todos, verb, show_all)
+        }
+    };
+    let resolve_done = |refs: &[TaskRef]| {
+        let mut refs = refs.to_vec();
+        if pick {
+            refs.push(TaskRef::Pick(dones.iter().map(|t| t.idx).collect()));
+        }
+        taskref::resolve(&refs, &dones, None)
+    };
+    let is_history_command = matches!(
+        command,
+        Command::UndoLast | Command::Redo | Command::History
+    );
+    if is_history_command {
+        autoarchive = false;
+    }
+    // Other commands only need the journal if they change something
+    let mut history = if is_history_command {
+        match history::History::load() {
+            Ok(history) => history,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        history::History::default()
+    };
+
+    // The other list a task was moved to, saved along with this one
+    let mut moved: Option<MovedTo> = None;
+    let mut opener = None;
+    let result = match command {
         // ------------------------------------------------------------
         //                         Add new tasks
         // ------------------------------------------------------------
-        Command::Add { text } => modify::add(&text, &mut todos),
-        Command::Addx { text } => modify::addx(&text, &mut todos),
-        Command::Adda { text } => modify::adda(&text, &mut todos),
-        Command::Addt { text } => modify::addt(&text, &mut todos),
+        Command::Add { text } => modify::add(&text, &mut todos, &dones),
+        Command::Addx { text } => modify::addx(&text, &mut todos, &dones),
+        Command::Adda { text, priority } => modify::adda(&text, &mut todos, &dones, &priority),
+        Command::Addt { text } => modify::addt(&text, &mut todos, &dones),
 
         // ------------------------------------------------------------
         //                     Modify existing tasks
         // ------------------------------------------------------------
-        Command::Append { idx, text } => modify::append(idx, &mut todos, &text),
-        Command::Prepend { idx, text } => modify::prepend(idx, &mut todos, &text),
+        Command::Append { idx, text } => {
+            resolve_one(&idx).and_then(|idx| modify::append(idx, &mut todos, &text))
+        }
+        Command::Prepend { idx, text } => {
+            resolve_one(&idx).and_then(|idx| modify::prepend(idx, &mut todos, &text))
+        }
+        Command::Replace { idx, text } => {
+            resolve_one(&idx).and_then(|idx| modify::replace(idx, &mut todos, &text))
+        }
+        Command::Subst {
+            pattern,
+            replacement,
+            dry_run,
+            yes,
+            filters,
+        } => modify::subst(&pattern, &replacement, &filters, &mut todos, dry_run, yes),
+        Command::Edit { idx: None } if !pick => modify::edit_all(&mut todos),
+        Command::Edit { idx } => resolve_one(&idx).and_then(|idx| modify::edit(idx, &mut todos)),
         Command::Prioritise { idx, priority } => {
-            modify::prioritise(idx, &mut todos, Some(priority))
+            resolve_one(&idx).and_then(|idx| modify::prioritise(idx, &mut todos, Some(priority)))
+        }
+        Command::Deprioritise { idx } => {
+            resolve_one(&idx).and_then(|idx| modify::prioritise(idx, &mut todos, None))
+        }
+        Command::PriUp { idxs } => {
+            resolve(&idxs).and_then(|idxs| modify::raise_priority_each(&idxs, &mut todos))
+        }
+        Command::PriDown { idxs } => {
+            resolve(&idxs).and_then(|idxs| modify::lower_priority_each(&idxs, &mut todos))
         }
-        Command::Deprioritise { idx } => modify::prioritise(idx, &mut todos, None),
 
         // ------------------------------------------------------------
         //                 Modify completion / existance
         // ------------------------------------------------------------
-        Command::Remove { idxs } => modify::remove(&idxs, &mut todos),
-        Command::Do { idxs } => modify::do_task(&idxs, &mut todos),
-        Command::Undo { idxs } => modify::undo(&idxs, &mut todos, &mut dones),
+        Command::Remove { idxs, bulk } => {
+            targets(&idxs, &bulk, "remove").and_then(|idxs| modify::remove(&idxs, &mut todos))
+        }
+        Command::Do { idxs, bulk } => targets(&idxs, &bulk, "finish")
+            .and_then(|idxs| modify::do_task(&idxs, &mut todos, &dones)),
+        Command::Undo { idxs } => {
+            resolve_done(&idxs).and_then(|idxs| modify::undo(&idxs, &mut todos, &mut dones))
+        }
+        Command::Move { idx, dest } => resolve_one(&idx)
+            .and_then(|idx| move_to_list(idx, &dest, &mut todos))
+            .map(|dest| moved = Some(dest)),
         Command::Archive => {
             autoarchive = false;
             modify::archive(&mut todos, &mut dones)
         }
 
         // ------------------------------------------------------------
-        //                          Scheduling
+        //                           History
         // ------------------------------------------------------------
-        Command::Schedule { idx, date } => modify::schedule(idx, &mut todos, &date),
-        Command::Unschedule { idxs } => modify::unschedule_each(&idxs, &mut todos),
-        Command::Today { idxs } => modify::schedule_each_today(&idxs, &mut todos),
+        Command::UndoLast => history.undo_last(&mut todos, &mut dones),
+        Command::Redo => history.redo(&mut todos, &mut dones),
+        Command::History => history.print(),
 
         // ------------------------------------------------------------
-        //                             Views
-        // ------------------------------------------------------------
-        Command::List { filters } => view::list(todos.iter(), &filters),
-        Command::ListPriority { filters } => view::list_priority(todos.iter(), &filters),
-        Command::ListDone { filters } => view::done(dones.iter(), &filters),
-        Command::Due { n_days, filters } => view::due(todos.iter(), n_days.unwrap_or(0), &filters),
-        Command::NoDate { filters } => view::no_date(todos.iter(), &filters),
-        Command::DoneSummary { days, filters } => view::done_summary(dones.iter(), &filters, days),
-
-        // ------------------------------------------------------------
-        //                       Views - Projects
-        // ------------------------------------------------------------
-        Command::Projects => view::projects(todos.iter()),
-        Command::Projectless => view::no_projects(todos.iter()),
-        Command::ProjectView { filters } => view::grouped_by_project(todos.iter(), &filters),
-
-        // ------------------------------------------------------------
-        //                         Views - Tags
+        //                          Scheduling
         // ------------------------------------------------------------
-        Command::Tags => view::tags(todos.iter()),
-        Command::Tagless => view::no_tags(todos.iter()),
-        Command::TagView { filters } => view::grouped_by_tag(todos.iter(), &filters),
+        Command::Schedule { idx, date } => {
+            resolve_one(&idx).and_then(|idx| modify::schedule(idx, &mut todos, &date.join(" ")))
+        }
+        Command::Unschedule { idxs, bulk } => targets(&idxs, &bulk, "unschedule")
+            .and_then(|idxs| modify::unschedule_each(&idxs, &mut todos)),
+        Command::Today { idxs, bulk } => targets(&idxs, &bulk, "schedule today")
+            .and_then(|idxs| modify::schedule_each_today(&idxs, &mut todos)),
 
         // ------------------------------------------------------------
         //                            Utility
         // ------------------------------------------------------------
-        Command::Link { indices } => utility::open_link(&todos, &indices),
-        Command::Links { filters } => view::links(todos.iter(), &filters),
+        Command::View { name: None, .. } => {
+            config.views.keys().for_each(|name| println!("{}", name));
+            Ok(())
+        }
+        Command::Export {
+            to,
+            done,
+            output,
+            filters,
+        } => {
+            let source = if done { &dones } else { &todos };
+            utility::todo_filter(source.iter(), &filters)
+                .and_then(|matching| interchange::export(matching, to))
+                .and_then(|exported| match output {
+                    Some(path) => utility::write_atomically(&path, &exported),
+                    None => {
+                        print!("{}", exported);
+                        Ok(())
+                    }
+                })
+        }
+        Command::Tui { filters } => tui::run(&mut todos, &dones, &filters),
+        Command::Calendar {
+            events,
+            output,
+            filters,
+        } => utility::todo_filter(todos.iter(), &filters)
+            .map(|matching| interchange::due_calendar(matching, events))
+            .and_then(|calendar| match output {
+                Some(path) => utility::write_atomically(&path, &calendar),
+                None => {
+                    print!("{}", calendar);
+                    Ok(())
+                }
+            }),
+        Command::Import { from, file } => utility::read_input(&file)
+            .and_then(|contents| modify::import(&contents, from, &mut todos)),
+        Command::Link { indices } => resolve(&indices)
+            .and_then(|indices| utility::open_link(&todos, &indices))
+            .map(|child| opener = Some(child)),
+        Command::Config | Command::Init { .. } => unreachable!("handled before loading tasks"),
+
+        // Everything else only shows tasks
+        ref view => show(view, &todos, &dones, show_all),
     };
 
     if let Err(err) = result {
         println!("{}", err);
         std::process::exit(1);
     }
+    if lists_todos {
+        listed.save(&todos)?;
+    }
 
     if autoarchive {
         if let Err(err) = modify::archive(&mut todos, &mut dones) {
@@ -251,8 +859,41 @@ fn main() -> Result<()> {
             std::process::exit(1);
         }
     }
-    utility::save_to_file(todos.iter(), std::env::var("TODOFILE")?)?;
-    utility::save_to_file(dones.iter(), std::env::var("DONEFILE")?)?;
+    let todos_changed = todos != todos_at_start;
+    let dones_changed = dones != dones_at_start;
+    if todos_changed {
+        todo_state.check_unchanged()?;
+    }
+    if dones_changed {
+        done_state.check_unchanged()?;
+    }
+    if let Some(dest) = &moved {
+        dest.state.check_unchanged()?;
+    }
+    if todos_changed {
+        utility::save_to_file(todos.iter(), config.todofile()?)?;
+    }
+    if dones_changed {
+        utility::save_to_file(dones.iter(), config.donefile()?)?;
+    }
+    if let Some(dest) = &moved {
+        utility::save_to_file(dest.todos.iter(), &dest.todofile)?;
+    }
+    if (todos_changed || dones_changed) && !is_history_command {
+        let command = utility::intersperse(std::env::args().skip(1), " ");
+        history = history::History::load()?;
+        history.record(
+            &command,
+            (&todos_at_start, &dones_at_start),
+            (&todos, &dones),
+        );
+        if moved.is_some() {
+            history.mark_other_list_changed();
+        }
+    }
+    if todos_changed || dones_changed {
+        history.save()?;
+    }
 
     if num_todos_at_start != 0 && todos.is_empty() {
         println!("TODOFILE is now empty");
@@ -260,5 +901,8 @@ fn main() -> Result<()> {
     if num_done_at_start != 0 && dones.is_empty() {
         println!("DONEFILE is now empty");
     }
+    // Links open in the background; there's no need to wait for them
+    drop(_lock);
+    drop(opener);
     Ok(())
 }
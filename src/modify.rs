@@ -1,33 +1,56 @@
 use super::{
+    colour,
+    interchange::{self, Format},
+    taskref,
     todo::{Todo, TodoPriority},
     utility,
 };
 
-type Result<T> = ::std::result::Result<T, Box<dyn (::std::error::Error)>>;
+use anyhow::anyhow;
+use regex::Regex;
 
-pub fn add(text: &str, todos: &mut Vec<Todo>) -> Result<()> {
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+pub fn add(text: &str, todos: &mut Vec<Todo>, dones: &[Todo]) -> Result<()> {
     let mut todo: Todo = text.parse()?;
     todo.idx = todos.len();
+    if todo.created_date.is_none() && utility::should_add_creation_date() {
+        todo.created_date = Some(utility::date_today().format("%Y-%m-%d").to_string());
+    }
+    if todo.extension("id").is_none() && utility::should_add_id() {
+        let id = taskref::next_id(todos.iter().chain(dones));
+        todo.set_extension("id", &id.to_string());
+    }
     utility::notify("ADDED", &todo);
     todos.push(todo);
     Ok(())
 }
 
-pub fn addx(text: &str, todos: &mut Vec<Todo>) -> Result<()> {
-    add(text, todos)?;
-    do_task(&[todos.len() - 1], todos)
+pub fn addx(text: &str, todos: &mut Vec<Todo>, dones: &[Todo]) -> Result<()> {
+    add(text, todos, dones)?;
+    do_task(&[todos.len() - 1], todos, dones)
 }
 
-pub fn adda(text: &str, todos: &mut Vec<Todo>) -> Result<()> {
-    add(text, todos)?;
-    prioritise(todos.len() - 1, todos, Some("A".to_string()))
+pub fn adda(text: &str, todos: &mut Vec<Todo>, dones: &[Todo], priority: &str) -> Result<()> {
+    // Check the priority first, so that a bad one doesn't leave an unprioritised task
+    priority.parse::<TodoPriority>()?;
+    add(text, todos, dones)?;
+    prioritise(todos.len() - 1, todos, Some(priority.to_string()))
 }
 
-pub fn addt(text: &str, todos: &mut Vec<Todo>) -> Result<()> {
-    add(text, todos)?;
+pub fn addt(text: &str, todos: &mut Vec<Todo>, dones: &[Todo]) -> Result<()> {
+    add(text, todos, dones)?;
     schedule(todos.len() - 1, todos, "today")
 }
 
+pub fn import(contents: &str, format: Format, todos: &mut Vec<Todo>) -> Result<()> {
+    for todo in interchange::import(contents, format, todos.len())? {
+        utility::notify("IMPORTED", &todo);
+        todos.push(todo);
+    }
+    Ok(())
+}
+
 pub fn append(idx: usize, todos: &mut [Todo], text: &str) -> Result<()> {
     if let Some(t) = todos.get_mut(idx) {
         t.append_text(text);
@@ -42,19 +65,162 @@ pub fn prepend(idx: usize, todos: &mut [Todo], text: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn prioritise(idx: usize, todos: &mut [Todo], priority: Option<String>) -> Result<()> {
+pub fn replace(idx: usize, todos: &mut [Todo], text: &str) -> Result<()> {
     if let Some(t) = todos.get_mut(idx) {
-        let priority = match priority.as_deref() {
-            Some("A" | "a") => TodoPriority::A,
-            Some("B" | "b") => TodoPriority::B,
-            Some("C" | "c") => TodoPriority::C,
-            _ => TodoPriority::None,
+        t.replace_text(text)?;
+        utility::notify("REPLACED", t);
+    }
+    Ok(())
+}
+
+/// Substitute `replacement` for each match of `pattern` in the tasks not yet
+/// done that match `filters`, showing the changes first and asking unless `yes`
+pub fn subst(
+    pattern: &str,
+    replacement: &str,
+    filters: &[String],
+    todos: &mut [Todo],
+    dry_run: bool,
+    yes: bool,
+) -> Result<()> {
+    let regex = Regex::new(pattern).map_err(|e| anyhow!("Bad pattern: {}", e))?;
+    let mut changed = Vec::new();
+    let matching = utility::todo_filter(todos.iter(), filters)?.filter(|t| t.done_date.is_none());
+    for todo in matching {
+        let description = todo.description();
+        let substituted = regex.replace_all(&description, replacement);
+        let new = todo
+            .with_description(&substituted)
+            .map_err(|e| anyhow!("Task {} would be `{}`: {}", todo.idx, substituted, e))?;
+        if new != *todo {
+            changed.push(new);
+        }
+    }
+    if changed.is_empty() {
+        println!("No tasks match `{}`", pattern);
+        return Ok(());
+    }
+
+    for new in &changed {
+        println!("{}", diff_line("-", &todos[new.idx], colour::red));
+        println!("{}", diff_line("+", new, colour::green));
+    }
+    if dry_run {
+        println!("{} tasks to change; nothing changed", changed.len());
+        return Ok(());
+    }
+    if !yes && !utility::confirm(&format!("Change {} tasks?", changed.len()))? {
+        println!("Nothing changed");
+        return Ok(());
+    }
+    for new in changed {
+        let idx = new.idx;
+        todos[idx] = new;
+    }
+    Ok(())
+}
+
+fn diff_line(sign: &str, todo: &Todo, paint: fn(&str) -> String) -> String {
+    let line = format!("{} {}", sign, todo.format_for_save());
+    if colour::should_colour() {
+        paint(&line)
+    } else {
+        line
+    }
+}
+
+/// Change a task's whole line in an editor
+pub fn edit(idx: usize, todos: &mut [Todo]) -> Result<()> {
+    let todo = todos.get(idx).ok_or_else(|| anyhow!("No task {}", idx))?;
+    let mut todo = edit_until_valid(&todo.format_for_save(), true)?.remove(0);
+    todo.idx = idx;
+    utility::notify("EDITED", &todo);
+    todos[idx] = todo;
+    Ok(())
+}
+
+/// Change every task in an editor, as if editing TODOFILE itself
+pub fn edit_all(todos: &mut Vec<Todo>) -> Result<()> {
+    let text: String = todos
+        .iter()
+        .map(|t| format!("{}\n", t.format_for_save()))
+        .collect();
+    *todos = edit_until_valid(&text, false)?;
+    Ok(())
+}
+
+/// Open the editor again, on what was written, for as long as it doesn't
+/// parse and the user wants to fix it
+fn edit_until_valid(text: &str, single: bool) -> Result<Vec<Todo>> {
+    let mut text = text.to_string();
+    loop {
+        text = utility::edit_text(&text)?;
+        let errors = match parse_edited(&text) {
+            Ok(todos) if !single || todos.len() == 1 && !todos[0].task.is_empty() => {
+                return Ok(todos)
+            }
+            Ok(_) => vec!["Expected one task; use `t rm` to remove it".to_string()],
+            Err(errors) => errors,
         };
+        errors.iter().for_each(|e| println!("{}", e));
+        if !utility::confirm("Edit again?")? {
+            return Err(anyhow!("Nothing changed").into());
+        }
+    }
+}
+
+/// The tasks in edited text, or what's wrong with each line that isn't one
+fn parse_edited(text: &str) -> std::result::Result<Vec<Todo>, Vec<String>> {
+    let mut todos = Vec::new();
+    let mut errors = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let parsed = line
+            .parse::<Todo>()
+            .map_err(|e| e.into())
+            .and_then(|todo| todo.check_dates().map(|_| todo));
+        match parsed {
+            Ok(mut todo) => {
+                todo.idx = idx;
+                todos.push(todo);
+            }
+            Err(e) => errors.push(format!("line {}: {}", idx + 1, e)),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(todos),
+        false => Err(errors),
+    }
+}
+
+pub fn prioritise(idx: usize, todos: &mut [Todo], priority: Option<String>) -> Result<()> {
+    let priority = match priority {
+        Some(p) => p.parse()?,
+        None => TodoPriority::None,
+    };
+    if let Some(t) = todos.get_mut(idx) {
         t.prioritise(priority);
     }
     Ok(())
 }
 
+pub fn raise_priority_each(indices: &[usize], todos: &mut [Todo]) -> Result<()> {
+    for &i in indices {
+        if let Some(t) = todos.get_mut(i) {
+            t.prioritise(t.pri.raised());
+        }
+    }
+    Ok(())
+}
+
+pub fn lower_priority_each(indices: &[usize], todos: &mut [Todo]) -> Result<()> {
+    for &i in indices {
+        if let Some(t) = todos.get_mut(i) {
+            t.prioritise(t.pri.lowered());
+        }
+    }
+    Ok(())
+}
+
 pub fn remove(indices: &[usize], todos: &mut Vec<Todo>) -> Result<()> {
     // reverse so that we always pop from the end of the list
     for &i in indices.iter().rev() {
@@ -68,8 +234,20 @@ pub fn remove(indices: &[usize], todos: &mut Vec<Todo>) -> Result<()> {
 
 pub fn schedule(idx: usize, todos: &mut [Todo], date: &str) -> Result<()> {
     if let Some(t) = todos.get_mut(idx) {
-        t.schedule(date);
+        t.schedule(date)?;
+    }
+    Ok(())
+}
+
+/// Move a task to the end of another list's tasks
+pub fn move_task(idx: usize, todos: &mut Vec<Todo>, target: &mut Vec<Todo>) -> Result<()> {
+    if idx >= todos.len() {
+        return Err(anyhow!("No task {}", idx).into());
     }
+    let mut todo = todos.remove(idx);
+    todo.idx = target.len();
+    utility::notify("MOVED", &todo);
+    target.push(todo);
     Ok(())
 }
 
@@ -109,12 +287,23 @@ pub fn archive(todos: &mut Vec<Todo>, dones: &mut Vec<Todo>) -> Result<()> {
     Ok(())
 }
 
-pub fn do_task(indices: &[usize], todos: &mut [Todo]) -> Result<()> {
+pub fn do_task(indices: &[usize], todos: &mut Vec<Todo>, dones: &[Todo]) -> Result<()> {
+    let mut recurring = Vec::new();
     indices.iter().rev().for_each(|&idx| {
         if let Some(t) = todos.get_mut(idx) {
-            t.mark_done()
+            recurring.extend(t.mark_done());
         }
     });
+    for mut next in recurring {
+        next.idx = todos.len();
+        // The finished task keeps its id, so the next one needs its own
+        if next.extension("id").is_some() {
+            let id = taskref::next_id(todos.iter().chain(dones));
+            next.set_extension("id", &id.to_string());
+        }
+        utility::notify("RECURRING", &next);
+        todos.push(next);
+    }
 
     Ok(())
 }
@@ -142,7 +331,7 @@ pub fn unschedule_each(indices: &[usize], todos: &mut [Todo]) -> Result<()> {
 pub fn schedule_each_today(indices: &[usize], todos: &mut [Todo]) -> Result<()> {
     for &i in indices.iter().rev() {
         if let Some(t) = todos.get_mut(i) {
-            t.schedule("today");
+            t.schedule("today")?;
         }
     }
     Ok(())
@@ -151,6 +340,24 @@ pub fn schedule_each_today(indices: &[usize], todos: &mut [Todo]) -> Result<()>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::todo::SourceLine;
+
+    #[test]
+    fn recurring_tasks_get_a_new_id() {
+        let mut todos = utility::parse_todos("water plants due:2021-09-01 rec:1w id:1\nb id:2");
+        let mut dones = utility::parse_todos("x 2021-09-01 old id:3");
+        do_task(&[0], &mut todos, &dones).unwrap();
+        assert_eq!(todos[2].extension("id"), Some("4"));
+        archive(&mut todos, &mut dones).unwrap();
+
+        let mut ids: Vec<&str> = todos
+            .iter()
+            .chain(&dones)
+            .filter_map(|t| t.extension("id"))
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec!["1", "2", "3", "4"]);
+    }
 
     #[test]
     fn can_prioritise_tasks() {
@@ -159,11 +366,62 @@ mod tests {
             task: "this is a test".to_string(),
             pri: TodoPriority::None,
             projects: vec!["+p1".to_string(), "+p2".to_string()],
-            contexts: vec!["@c1".to_string()],
+            tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         }];
         prioritise(0, &mut tasks, Some("A".to_string())).unwrap();
-        assert_eq!(tasks[0].pri, TodoPriority::A)
+        assert_eq!(tasks[0].pri, TodoPriority::A);
+
+        prioritise(0, &mut tasks, Some("d".to_string())).unwrap();
+        assert_eq!(tasks[0].pri, TodoPriority::D);
+
+        assert!(prioritise(0, &mut tasks, Some("3".to_string())).is_err());
+        assert_eq!(tasks[0].pri, TodoPriority::D);
+
+        raise_priority_each(&[0], &mut tasks).unwrap();
+        assert_eq!(tasks[0].pri, TodoPriority::C);
+        lower_priority_each(&[0, 0], &mut tasks).unwrap();
+        assert_eq!(tasks[0].pri, TodoPriority::E);
+    }
+
+    #[test]
+    fn can_substitute_across_tasks() {
+        let mut todos =
+            utility::parse_todos("(B) call bob +wrk\nbuy milk +wrk\nx 2021-09-13 email bob +wrk");
+        let filters = vec!["-milk".to_string()];
+        subst(r"\+wrk\b", "+work", &filters, &mut todos, false, true).unwrap();
+        assert_eq!(todos[0].format_for_save(), "(B) call bob +work");
+        assert_eq!(todos[1].format_for_save(), "buy milk +wrk");
+        assert_eq!(todos[2].format_for_save(), "x 2021-09-13 email bob +wrk");
+
+        subst("(call) (bob)", "$2 $1", &[], &mut todos, true, true).unwrap();
+        assert_eq!(todos[0].task, "call bob");
+        assert!(subst("(", "", &[], &mut todos, false, true).is_err());
+
+        let mut todos = utility::parse_todos("(A) 2021-09-01 call bob due:2021-10-01 rec:1w +wrk");
+        subst(r" ?(due|rec):\S+", "", &[], &mut todos, false, true).unwrap();
+        assert_eq!(todos[0].format_for_save(), "(A) 2021-09-01 call bob +wrk");
+        assert_eq!((todos[0].due_date.as_ref(), todos[0].rec), (None, None));
+    }
+
+    #[test]
+    fn edited_text_must_parse() {
+        let todos = parse_edited("(A) call bob +work\n\nx 2021-09-13 buy milk").unwrap();
+        assert_eq!(todos.len(), 3);
+        assert_eq!(todos[2].idx, 2);
+
+        let errors = parse_edited("call bob due:friday\nfine\nx tuesday buy milk").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "line 1: due date `friday` isn't a YYYY-MM-DD date",
+                "line 3: done date `tuesday` isn't a YYYY-MM-DD date"
+            ]
+        );
     }
 }
@@ -0,0 +1,215 @@
+use super::{todo::Todo, tui};
+
+use anyhow::anyhow;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use std::collections::BTreeSet;
+use std::io::Write;
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+/// Choose from the tasks at indices `from` with a fuzzy finder, returning
+/// their indices in order.
+///
+/// Typing narrows the list, Tab marks several tasks if `multi`, and Enter
+/// picks the marked tasks or else the highlighted one.
+pub fn pick(todos: &[Todo], from: &[usize], multi: bool) -> Result<Vec<usize>> {
+    if from.is_empty() {
+        return Err(anyhow!("No tasks to pick from").into());
+    }
+    let mut picker = Picker::new(multi);
+    match tui::with_terminal(|out| picker.event_loop(out, todos, from))? {
+        Some(picked) => Ok(picked),
+        None => Err(anyhow!("No task picked").into()),
+    }
+}
+
+struct Picker {
+    query: String,
+    /// Position of the highlighted task among the matches
+    selected: usize,
+    marked: BTreeSet<usize>,
+    multi: bool,
+    matcher: SkimMatcherV2,
+}
+
+impl Picker {
+    fn new(multi: bool) -> Picker {
+        Picker {
+            query: String::new(),
+            selected: 0,
+            marked: BTreeSet::new(),
+            multi,
+            matcher: SkimMatcherV2::default().ignore_case(),
+        }
+    }
+
+    fn event_loop(
+        &mut self,
+        out: &mut impl Write,
+        todos: &[Todo],
+        from: &[usize],
+    ) -> Result<Option<Vec<usize>>> {
+        loop {
+            let matches = self.matches(todos, from);
+            self.draw(out, todos, from, &matches)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(outcome) = self.handle(key, &matches) {
+                    return Ok(outcome);
+                }
+            }
+        }
+    }
+
+    /// Indices of the tasks in `from` matching the query, best match first
+    fn matches(&self, todos: &[Todo], from: &[usize]) -> Vec<usize> {
+        let mut scored: Vec<(i64, usize)> = from
+            .iter()
+            .filter_map(|&i| {
+                let score = self
+                    .matcher
+                    .fuzzy_match(&todos.get(i)?.format_for_save(), &self.query)?;
+                Some((score, i))
+            })
+            .collect();
+        // Stable, so equally good matches stay in file order
+        scored.sort_by_key(|&(score, _)| -score);
+        scored.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// What was picked, `Some(None)` if cancelled, or `None` to keep going
+    fn handle(&mut self, key: KeyEvent, matches: &[usize]) -> Option<Option<Vec<usize>>> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let last = matches.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Esc => return Some(None),
+            KeyCode::Char('c') if ctrl => return Some(None),
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('p') | KeyCode::Char('k') if ctrl => {
+                self.selected = self.selected.saturating_sub(1)
+            }
+            KeyCode::Down => self.selected = (self.selected + 1).min(last),
+            KeyCode::Char('n') | KeyCode::Char('j') if ctrl => {
+                self.selected = (self.selected + 1).min(last)
+            }
+            KeyCode::Tab if self.multi => {
+                if let Some(&idx) = matches.get(self.selected) {
+                    if !self.marked.remove(&idx) {
+                        self.marked.insert(idx);
+                    }
+                    self.selected = (self.selected + 1).min(last);
+                }
+            }
+            KeyCode::Enter if !self.marked.is_empty() => {
+                return Some(Some(self.marked.iter().copied().collect()))
+            }
+            KeyCode::Enter => return matches.get(self.selected).map(|&idx| Some(vec![idx])),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.selected = 0;
+            }
+            _ => (),
+        }
+        None
+    }
+
+    fn draw(
+        &self,
+        out: &mut impl Write,
+        todos: &[Todo],
+        from: &[usize],
+        matches: &[usize],
+    ) -> Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, rows) = (width as usize, (height as usize).saturating_sub(2));
+        let selected = self.selected.min(matches.len().saturating_sub(1));
+        let top = (selected + 1).saturating_sub(rows);
+
+        queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        queue!(
+            out,
+            Print(tui::truncate(&format!("> {}", self.query), width))
+        )?;
+        let hint = if self.multi {
+            "  Tab marks several, Enter picks, Esc cancels"
+        } else {
+            "  Enter picks, Esc cancels"
+        };
+        let status = format!("  {}/{}{}", matches.len(), from.len(), hint);
+        queue!(
+            out,
+            cursor::MoveTo(0, 1),
+            Print(tui::truncate(&status, width))
+        )?;
+        for (row, &idx) in matches.iter().enumerate().skip(top).take(rows) {
+            let mark = if self.marked.contains(&idx) { '*' } else { ' ' };
+            let line = tui::truncate(&format!("{}{}", mark, todos[idx]), width);
+            queue!(out, cursor::MoveTo(0, (row - top + 2) as u16))?;
+            if row == selected {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(format!("{:width$}", line, width = width)),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility;
+
+    fn press(picker: &mut Picker, todos: &[Todo], keys: &[KeyCode]) -> Option<Option<Vec<usize>>> {
+        let from: Vec<usize> = (0..todos.len()).collect();
+        keys.iter()
+            .map(|&code| picker.handle(KeyEvent::from(code), &picker.matches(todos, &from)))
+            .last()
+            .flatten()
+    }
+
+    #[test]
+    fn can_pick_several_tasks() {
+        let todos = utility::parse_todos("call bob +work\nbuy milk @shop\nwrite report +work");
+        let mut picker = Picker::new(true);
+        picker.query = "wrk".to_string();
+        assert_eq!(picker.matches(&todos, &[0, 1, 2]), vec![0, 2]);
+        assert_eq!(picker.matches(&todos, &[1, 2]), vec![2]);
+
+        let keys = [KeyCode::Down, KeyCode::Tab, KeyCode::Up, KeyCode::Tab];
+        assert_eq!(press(&mut picker, &todos, &keys), None);
+        assert_eq!(
+            press(&mut picker, &todos, &[KeyCode::Enter]),
+            Some(Some(vec![0, 2]))
+        );
+
+        let mut single = Picker::new(false);
+        let keys = [
+            KeyCode::Char('m'),
+            KeyCode::Char('l'),
+            KeyCode::Tab,
+            KeyCode::Enter,
+        ];
+        assert_eq!(press(&mut single, &todos, &keys), Some(Some(vec![1])));
+        assert_eq!(press(&mut single, &todos, &[KeyCode::Esc]), Some(None));
+    }
+}
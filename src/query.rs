@@ -0,0 +1,270 @@
+use super::{todo::Todo, utility};
+
+use anyhow::anyhow;
+use std::cmp::Ordering;
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+/// A filter expression, such as `+work and (pri:A or due<=today) and not @phone`.
+///
+/// Plain words are matched as they always have been, and are implicitly
+/// joined with `and`; a leading `-` negates a word.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Term(String),
+    Compare {
+        key: String,
+        op: CompareOp,
+        value: String,
+    },
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+impl Query {
+    pub fn parse(filters: &[String]) -> Result<Query> {
+        let tokens = lex(filters);
+        let mut pos = 0;
+        let query = parse_or(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            None => Ok(query),
+            Some(token) => Err(anyhow!("Unexpected {:?} in filter", token).into()),
+        }
+    }
+
+    pub fn matches(&self, todo: &Todo) -> bool {
+        match self {
+            Query::Term(term) => todo.matches_term(term),
+            Query::Compare { key, op, value } => compare(todo, key, *op, value),
+            Query::Not(q) => !q.matches(todo),
+            Query::And(qs) => qs.iter().all(|q| q.matches(todo)),
+            Query::Or(qs) => qs.iter().any(|q| q.matches(todo)),
+        }
+    }
+}
+
+fn lex(filters: &[String]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for filter in filters {
+        let mut word = String::new();
+        let flush = |word: &mut String, tokens: &mut Vec<Token>| {
+            if !word.is_empty() {
+                tokens.push(match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word.clone()),
+                });
+                word.clear();
+            }
+        };
+        for c in filter.chars() {
+            match c {
+                '(' if word.is_empty() || word == "-" => {
+                    if word == "-" {
+                        tokens.push(Token::Not);
+                        word.clear();
+                    }
+                    tokens.push(Token::Open);
+                }
+                ')' if !word.contains('(') => {
+                    flush(&mut word, &mut tokens);
+                    tokens.push(Token::Close);
+                }
+                c if c.is_whitespace() => flush(&mut word, &mut tokens),
+                c => word.push(c),
+            }
+        }
+        flush(&mut word, &mut tokens);
+    }
+    tokens
+}
+
+fn parse_or(tokens: &[Token], pos: &mut usize) -> Result<Query> {
+    let mut terms = vec![parse_and(tokens, pos)?];
+    while tokens.get(*pos) == Some(&Token::Or) {
+        *pos += 1;
+        terms.push(parse_and(tokens, pos)?);
+    }
+    Ok(if terms.len() == 1 {
+        terms.remove(0)
+    } else {
+        Query::Or(terms)
+    })
+}
+
+fn parse_and(tokens: &[Token], pos: &mut usize) -> Result<Query> {
+    let mut terms = Vec::new();
+    loop {
+        match tokens.get(*pos) {
+            None | Some(Token::Close) | Some(Token::Or) => break,
+            Some(Token::And) => *pos += 1,
+            Some(_) => terms.push(parse_unary(tokens, pos)?),
+        }
+    }
+    Ok(if terms.len() == 1 {
+        terms.remove(0)
+    } else {
+        Query::And(terms)
+    })
+}
+
+fn parse_unary(tokens: &[Token], pos: &mut usize) -> Result<Query> {
+    let token = tokens
+        .get(*pos)
+        .ok_or_else(|| anyhow!("Filter ended unexpectedly"))?;
+    *pos += 1;
+    match token {
+        Token::Not => Ok(Query::Not(Box::new(parse_unary(tokens, pos)?))),
+        Token::Open => {
+            let query = parse_or(tokens, pos)?;
+            if tokens.get(*pos) != Some(&Token::Close) {
+                return Err(anyhow!("Missing `)` in filter").into());
+            }
+            *pos += 1;
+            Ok(query)
+        }
+        Token::Word(word) => Ok(match word.strip_prefix('-') {
+            Some(rest) if !rest.is_empty() => Query::Not(Box::new(parse_word(rest))),
+            _ => parse_word(word),
+        }),
+        token => Err(anyhow!("Unexpected {:?} in filter", token).into()),
+    }
+}
+
+fn parse_word(word: &str) -> Query {
+    let ops = [
+        ("<=", CompareOp::Le),
+        (">=", CompareOp::Ge),
+        ("!=", CompareOp::Ne),
+        ("<", CompareOp::Lt),
+        (">", CompareOp::Gt),
+        ("=", CompareOp::Eq),
+    ];
+    for (symbol, op) in ops.iter() {
+        if let Some((key, value)) = word.split_once(symbol) {
+            let valid_key = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if valid_key && !value.is_empty() {
+                return Query::Compare {
+                    key: key.to_string(),
+                    op: *op,
+                    value: value.to_string(),
+                };
+            }
+        }
+    }
+    Query::Term(word.to_string())
+}
+
+const DATE_KEYS: [&str; 4] = ["due", "t", "created", "done"];
+
+/// Compare a task's value for `key`; tasks without the key never match.
+///
+/// Dates may be given relative to today, priorities compare alphabetically,
+/// and anything else compares as a number if it can.
+fn compare(todo: &Todo, key: &str, op: CompareOp, value: &str) -> bool {
+    let actual = match todo.key_value(key) {
+        Some(actual) => actual,
+        None => return false,
+    };
+    let ordering = if DATE_KEYS.contains(&key) {
+        let wanted = utility::parse_date(utility::date_today(), value).ok();
+        match (utility::parse_ymd(&actual), wanted) {
+            (Some(a), Some(w)) => a.cmp(&w),
+            _ => return false,
+        }
+    } else {
+        match (actual.parse::<f64>(), value.parse::<f64>()) {
+            (Ok(a), Ok(w)) => match a.partial_cmp(&w) {
+                Some(ordering) => ordering,
+                None => return false,
+            },
+            _ => actual.to_ascii_lowercase().cmp(&value.to_ascii_lowercase()),
+        }
+    };
+    match op {
+        CompareOp::Lt => ordering == Ordering::Less,
+        CompareOp::Le => ordering != Ordering::Greater,
+        CompareOp::Gt => ordering == Ordering::Greater,
+        CompareOp::Ge => ordering != Ordering::Less,
+        CompareOp::Eq => ordering == Ordering::Equal,
+        CompareOp::Ne => ordering != Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(s: &str) -> Query {
+        Query::parse(&[s.to_string()]).unwrap()
+    }
+
+    #[test]
+    fn can_parse_queries() {
+        assert_eq!(
+            query("+work and (pri:A or due<=today) and not @phone"),
+            Query::And(vec![
+                Query::Term("+work".to_string()),
+                Query::Or(vec![
+                    Query::Term("pri:A".to_string()),
+                    Query::Compare {
+                        key: "due".to_string(),
+                        op: CompareOp::Le,
+                        value: "today".to_string()
+                    },
+                ]),
+                Query::Not(Box::new(Query::Term("@phone".to_string()))),
+            ])
+        );
+        assert!(Query::parse(&["(+work or".to_string()]).is_err());
+        assert!(Query::parse(&["+work)".to_string()]).is_err());
+    }
+
+    #[test]
+    fn bare_words_behave_as_before() {
+        let filters = vec!["report".to_string(), "-@home".to_string()];
+        let q = Query::parse(&filters).unwrap();
+        let t: Todo = "write report +work @office".parse().unwrap();
+        assert!(q.matches(&t));
+        let t: Todo = "write report +work @home".parse().unwrap();
+        assert!(!q.matches(&t));
+    }
+
+    #[test]
+    fn can_evaluate_comparisons() {
+        let t: Todo = "(B) write report due:2021-09-13 est:3 +work"
+            .parse()
+            .unwrap();
+        assert!(query("+work and (pri:A or due<=today)").matches(&t));
+        assert!(!query("due<today").matches(&t));
+        assert!(query("pri<=B est>2").matches(&t));
+        assert!(!query("pri<B or est>=10").matches(&t));
+        assert!(!query("t<=today").matches(&t));
+        assert!(query("-(pri:A or @phone)").matches(&t));
+    }
+}
@@ -0,0 +1,182 @@
+use super::{config, pick, todo::Todo, utility};
+
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+/// A task given on the command line, either by line index or as `id:ID`, or
+/// to be chosen with a fuzzy finder from the tasks at the given indices
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskRef {
+    Index(usize),
+    Id(String),
+    Pick(Vec<usize>),
+}
+
+impl FromStr for TaskRef {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(id) = s.strip_prefix("id:").filter(|id| !id.is_empty()) {
+            return Ok(TaskRef::Id(id.to_string()));
+        }
+        s.parse()
+            .map(TaskRef::Index)
+            .map_err(|_| anyhow!("Expected a task index or `id:ID`, got `{}`", s))
+    }
+}
+
+/// What each index pointed at when tasks were last shown by a view, so that
+/// commands can refuse to act on an index that has since moved.
+#[derive(Debug, Default)]
+pub struct Listed {
+    path: Option<PathBuf>,
+    tasks: HashMap<usize, String>,
+}
+
+impl Listed {
+    pub fn load() -> Result<Listed> {
+        let path = utility::hidden_sibling(config::get().todofile()?, "listed")?;
+        let tasks = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let (idx, fingerprint) = line.split_once('\t')?;
+                Some((idx.parse().ok()?, fingerprint.to_string()))
+            })
+            .collect();
+        Ok(Listed {
+            path: Some(path),
+            tasks,
+        })
+    }
+
+    pub fn save(&mut self, todos: &[Todo]) -> Result<()> {
+        self.tasks = todos.iter().map(|t| (t.idx, fingerprint(t))).collect();
+        let mut lines: Vec<_> = self.tasks.iter().collect();
+        lines.sort();
+        let contents =
+            utility::intersperse(lines.iter().map(|(idx, f)| format!("{}\t{}\n", idx, f)), "");
+        match &self.path {
+            Some(path) => utility::write_atomically(path, &contents),
+            None => Ok(()),
+        }
+    }
+
+    fn check(&self, idx: usize, todos: &[Todo]) -> Result<()> {
+        let listed = match self.tasks.get(&idx) {
+            Some(listed) => listed,
+            None => return Ok(()),
+        };
+        match todos.get(idx) {
+            Some(t) if fingerprint(t) == *listed => Ok(()),
+            Some(t) => Err(anyhow!(
+                "Task {} is now `{}`, not `{}` as when last listed. List again, or use its id.",
+                idx,
+                t.task,
+                listed
+            )
+            .into()),
+            None => Err(anyhow!("Task {} (`{}`) no longer exists", idx, listed).into()),
+        }
+    }
+}
+
+// Tasks are recognised by their id if they have one, otherwise their text
+fn fingerprint(todo: &Todo) -> String {
+    match todo.extension("id") {
+        Some(id) => format!("id:{}", id),
+        None => todo.task.clone(),
+    }
+}
+
+/// Turn task references into indices into `todos`.
+///
+/// Indices are checked against `listed`, if given; ids are looked up directly;
+/// and `Pick` asks for any number of tasks. The result is sorted without
+/// duplicates, whatever order the tasks were given in.
+pub fn resolve(refs: &[TaskRef], todos: &[Todo], listed: Option<&Listed>) -> Result<Vec<usize>> {
+    let mut indices = Vec::new();
+    for r in refs {
+        match r {
+            TaskRef::Index(idx) => {
+                if let Some(listed) = listed {
+                    listed.check(*idx, todos)?;
+                }
+                indices.push(*idx);
+            }
+            TaskRef::Id(id) => indices.push(
+                todos
+                    .iter()
+                    .position(|t| t.extension("id") == Some(id.as_str()))
+                    .ok_or_else(|| anyhow!("No task with id:{}", id))?,
+            ),
+            TaskRef::Pick(from) => indices.extend(pick::pick(todos, from, true)?),
+        }
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    Ok(indices)
+}
+
+/// Like `resolve`, for commands that change a single task
+pub fn resolve_one(r: &TaskRef, todos: &[Todo], listed: Option<&Listed>) -> Result<usize> {
+    match r {
+        TaskRef::Pick(from) => Ok(pick::pick(todos, from, false)?[0]),
+        r => Ok(resolve(std::slice::from_ref(r), todos, listed)?[0]),
+    }
+}
+
+/// The next unused numeric id, across both todos and dones
+pub fn next_id<'a>(todos: impl Iterator<Item = &'a Todo>) -> usize {
+    todos
+        .filter_map(|t| t.extension("id")?.parse::<usize>().ok())
+        .max()
+        .map_or(1, |max| max + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_resolve_ids_and_indices() {
+        let todos = utility::parse_todos("first id:1\nsecond id:3\nthird");
+        let refs: Vec<TaskRef> = vec!["id:3".parse().unwrap(), "2".parse().unwrap()];
+        assert_eq!(resolve(&refs, &todos, None).unwrap(), vec![1, 2]);
+        assert!(resolve(&["id:9".parse().unwrap()], &todos, None).is_err());
+        assert!("three".parse::<TaskRef>().is_err());
+        assert_eq!(next_id(todos.iter()), 4);
+    }
+
+    #[test]
+    fn removes_each_task_once_whatever_the_order() {
+        let mut todos = utility::parse_todos("a id:1\nb id:2\nc id:3\nd id:4");
+        let refs: Vec<TaskRef> = ["id:4", "id:2", "3", "1"]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect();
+        let indices = resolve(&refs, &todos, None).unwrap();
+        assert_eq!(indices, vec![1, 3]);
+        crate::modify::remove(&indices, &mut todos).unwrap();
+        let left: Vec<&str> = todos.iter().map(|t| t.task.as_str()).collect();
+        assert_eq!(left, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn refuses_indices_that_have_moved() {
+        let mut listed = Listed::default();
+        listed
+            .save(&utility::parse_todos("first\nsecond id:3\nthird"))
+            .unwrap();
+
+        let todos = utility::parse_todos("(A) first due:2021-09-13\nthird");
+        assert!(resolve(&[TaskRef::Index(0)], &todos, Some(&listed)).is_ok());
+        assert!(resolve(&[TaskRef::Index(1)], &todos, Some(&listed)).is_err());
+        assert!(resolve(&[TaskRef::Index(2)], &todos, Some(&listed)).is_err());
+        assert!(resolve(&[TaskRef::Index(5)], &todos, Some(&listed)).is_ok());
+    }
+}
@@ -1,8 +1,12 @@
-use chrono::{Date, NaiveDate, Utc};
+use chrono::{Date, Duration, NaiveDate, TimeZone, Utc};
 
 use super::{colour, utility};
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::fmt::{self, Display};
 use std::str::FromStr;
 
@@ -15,7 +19,31 @@ pub struct Todo {
     pub projects: Vec<String>,
     pub tags: Vec<String>,
     pub done_date: Option<String>,
+    pub created_date: Option<String>,
     pub due_date: Option<String>,
+    pub rec: Option<Recurrence>,
+    /// Any other `key:value` tokens, in the order they appeared
+    pub extensions: Vec<(String, String)>,
+    pub source: SourceLine,
+}
+
+/// The line a task was parsed from, so that saving can keep its layout.
+///
+/// This is bookkeeping rather than part of the task, so it's ignored when
+/// comparing tasks.
+#[derive(Clone, Debug, Default)]
+pub struct SourceLine(pub Option<String>);
+
+impl PartialEq for SourceLine {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl PartialOrd for SourceLine {
+    fn partial_cmp(&self, _other: &Self) -> Option<Ordering> {
+        Some(Ordering::Equal)
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy)]
@@ -114,9 +142,143 @@ impl FromStr for TodoPriority {
             "X" => Ok(TodoPriority::X),
             "Y" => Ok(TodoPriority::Y),
             "Z" => Ok(TodoPriority::Z),
-            _ => Ok(TodoPriority::None),
+            _ => Err(anyhow::anyhow!(
+                "Priority must be a letter A-Z, not `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl TodoPriority {
+    const LETTERS: [TodoPriority; 26] = [
+        TodoPriority::A,
+        TodoPriority::B,
+        TodoPriority::C,
+        TodoPriority::D,
+        TodoPriority::E,
+        TodoPriority::F,
+        TodoPriority::G,
+        TodoPriority::H,
+        TodoPriority::I,
+        TodoPriority::J,
+        TodoPriority::K,
+        TodoPriority::L,
+        TodoPriority::M,
+        TodoPriority::N,
+        TodoPriority::O,
+        TodoPriority::P,
+        TodoPriority::Q,
+        TodoPriority::R,
+        TodoPriority::S,
+        TodoPriority::T,
+        TodoPriority::U,
+        TodoPriority::V,
+        TodoPriority::W,
+        TodoPriority::X,
+        TodoPriority::Y,
+        TodoPriority::Z,
+    ];
+
+    pub fn letter(self) -> Option<char> {
+        let i = TodoPriority::LETTERS.iter().position(|&p| p == self)?;
+        Some((b'A' + i as u8) as char)
+    }
+
+    /// One letter more important; A stays A, and no priority becomes Z
+    pub fn raised(self) -> TodoPriority {
+        match TodoPriority::LETTERS.iter().position(|&p| p == self) {
+            Some(i) => TodoPriority::LETTERS[i.saturating_sub(1)],
+            None => TodoPriority::Z,
         }
     }
+
+    /// One letter less important; Z loses its priority
+    pub fn lowered(self) -> TodoPriority {
+        match TodoPriority::LETTERS.iter().position(|&p| p == self) {
+            Some(i) => TodoPriority::LETTERS
+                .get(i + 1)
+                .copied()
+                .unwrap_or(TodoPriority::None),
+            None => TodoPriority::None,
+        }
+    }
+}
+
+/// How often a task repeats, from a `rec:` token such as `rec:1w` or `rec:+3d`.
+///
+/// A leading `+` makes the recurrence strict: the next due date is counted
+/// from the old due date rather than from the day the task was completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
+pub struct Recurrence {
+    pub strict: bool,
+    pub n: u32,
+    pub unit: RecurrenceUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
+pub enum RecurrenceUnit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Recurrence {
+    /// The next date, or `None` if it's beyond the dates that can be represented
+    pub fn next_after(&self, date: Date<Utc>) -> Option<Date<Utc>> {
+        match self.unit {
+            RecurrenceUnit::Day => date.checked_add_signed(Duration::days(self.n.into())),
+            RecurrenceUnit::Week => date.checked_add_signed(Duration::weeks(self.n.into())),
+            RecurrenceUnit::Month => utility::add_months(date, self.n),
+            RecurrenceUnit::Year => utility::add_months(date, self.n.checked_mul(12)?),
+        }
+    }
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.unit {
+            RecurrenceUnit::Day => 'd',
+            RecurrenceUnit::Week => 'w',
+            RecurrenceUnit::Month => 'm',
+            RecurrenceUnit::Year => 'y',
+        };
+        let strict = if self.strict { "+" } else { "" };
+        write!(f, "{}{}{}", strict, self.n, unit)
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (strict, rest) = match s.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let unit = match rest.chars().last() {
+            Some('d') => RecurrenceUnit::Day,
+            Some('w') => RecurrenceUnit::Week,
+            Some('m') => RecurrenceUnit::Month,
+            Some('y') => RecurrenceUnit::Year,
+            _ => return Err(anyhow::anyhow!("Unknown recurrence unit in `{}`", s)),
+        };
+        let n: u32 = rest[..rest.len() - 1]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Bad recurrence interval in `{}`", s))?;
+        if n == 0 {
+            return Err(anyhow::anyhow!("Recurrence interval must be positive"));
+        }
+        let rec = Recurrence { strict, n, unit };
+        // Due dates have four-digit years, so any of them must have a next one
+        if rec.next_after(Utc.ymd(9999, 12, 31)).is_none() {
+            return Err(anyhow::anyhow!(
+                "Recurrence interval in `{}` is too long",
+                s
+            ));
+        }
+        Ok(rec)
+    }
 }
 
 impl Todo {
@@ -127,21 +289,66 @@ impl Todo {
             .contains(&needle.to_string().to_ascii_lowercase())
     }
 
-    pub fn matches(&self, positives: &[impl ToString], negatives: &[impl ToString]) -> bool {
-        let taskstr = format!(
-            "{}{}{}",
-            self.task,
-            &self.projects.join(" "),
-            &self.tags.join(" ")
-        );
+    /// Match a single filter word, either `key:value`, `key:`, a whole
+    /// project or context (including sub-projects), or plain text
+    pub fn matches_term(&self, needle: &str) -> bool {
+        if needle.len() > 1 && (needle.starts_with('+') || needle.starts_with('@')) {
+            return self
+                .projects
+                .iter()
+                .chain(self.tags.iter())
+                .any(|p| is_within(p, needle));
+        }
+        match split_key_value(needle) {
+            Some((key, "")) => self.key_value(key).is_some(),
+            Some((key, value)) => self
+                .key_value(key)
+                .map(|v| v.eq_ignore_ascii_case(value))
+                .unwrap_or(false),
+            None => {
+                let taskstr = format!(
+                    "{}{}{}",
+                    self.task,
+                    &self.projects.join(" "),
+                    &self.tags.join(" ")
+                );
+                Todo::case_insensitive_match(&taskstr, &needle)
+            }
+        }
+    }
 
-        let has_no_neg = !negatives
-            .iter()
-            .any(|y| Todo::case_insensitive_match(&taskstr, y));
-        let has_all_pos = positives
+    /// Look up the value of a `key:value` token, including `due:` and `rec:`,
+    /// as well as the priority, done and creation dates
+    pub fn key_value(&self, key: &str) -> Option<String> {
+        match key {
+            "due" => self.due_date.clone(),
+            "rec" => self.rec.map(|r| r.to_string()),
+            "done" => self.done_date.clone(),
+            "created" => self.created_date.clone(),
+            "pri" if self.pri != TodoPriority::None => self.pri.letter().map(String::from),
+            _ => self.extension(key).map(|v| v.to_string()),
+        }
+    }
+
+    pub fn extension(&self, key: &str) -> Option<&str> {
+        self.extensions
             .iter()
-            .all(|y| Todo::case_insensitive_match(&taskstr, y));
-        has_all_pos && has_no_neg
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_extension(&mut self, key: &str, value: &str) {
+        match self.extensions.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.extensions.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    fn extensions_string(&self) -> String {
+        utility::intersperse(
+            self.extensions.iter().map(|(k, v)| format!("{}:{}", k, v)),
+            " ",
+        )
     }
 
     pub fn append_text(&mut self, text: &str) {
@@ -155,6 +362,44 @@ impl Todo {
         utility::notify("PREPENDED", &self);
     }
 
+    /// Replace the text, projects and tags, keeping the priority, dates and
+    /// `key:value` metadata unless `text` gives new ones
+    pub fn replace_text(&mut self, text: &str) -> super::Result<()> {
+        let new: Todo = text.parse()?;
+        new.check_dates()?;
+        self.task = new.task;
+        self.projects = new.projects;
+        self.tags = new.tags;
+        if new.pri != TodoPriority::None {
+            self.pri = new.pri;
+        }
+        self.due_date = new.due_date.or_else(|| self.due_date.take());
+        self.rec = new.rec.or(self.rec);
+        for (key, value) in &new.extensions {
+            self.set_extension(key, value);
+        }
+        Ok(())
+    }
+
+    /// The line as saved, without the done marker, priority or creation date
+    pub fn description(&self) -> String {
+        let line = self.format_for_save();
+        match line.strip_prefix(&self.prefix_string()) {
+            Some(rest) => rest.trim().to_string(),
+            None => line,
+        }
+    }
+
+    /// This task with everything but its done marker, priority and creation
+    /// date read afresh from `description`, so metadata left out is dropped
+    pub fn with_description(&self, description: &str) -> super::Result<Todo> {
+        let line = utility::join_non_empty([self.prefix_string().as_str(), description].iter());
+        let mut todo: Todo = line.parse()?;
+        todo.check_dates()?;
+        todo.idx = self.idx;
+        Ok(todo)
+    }
+
     pub fn prioritise(&mut self, priority: TodoPriority) {
         self.pri = priority;
         if !matches!(self.pri, TodoPriority::None) {
@@ -164,10 +409,59 @@ impl Todo {
         }
     }
 
-    pub fn mark_done(&mut self) {
+    /// Whether the due and done dates are real `YYYY-MM-DD` dates
+    pub fn check_dates(&self) -> super::Result<()> {
+        for (what, date) in [("due", &self.due_date), ("done", &self.done_date)] {
+            if let Some(date) = date.as_ref().filter(|d| utility::parse_ymd(d).is_none()) {
+                return Err(
+                    anyhow::anyhow!("{} date `{}` isn't a YYYY-MM-DD date", what, date).into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark the task as done, returning the next occurrence if it recurs.
+    pub fn mark_done(&mut self) -> Option<Todo> {
+        let next = self.next_occurrence();
         self.done_date = Some(utility::date_today().format("%Y-%m-%d").to_string());
         self.pri = TodoPriority::None;
         utility::notify("DONE", &self);
+        next
+    }
+
+    fn next_occurrence(&self) -> Option<Todo> {
+        let rec = self.rec?;
+        if self.done_date.is_some() {
+            return None;
+        }
+        let today = utility::date_today();
+        let base = match (rec.strict, self.due_date()) {
+            (true, Some(due)) => due,
+            _ => today,
+        };
+        let mut next = self.clone();
+        if next.created_date.is_some() {
+            next.created_date = Some(today.format("%Y-%m-%d").to_string());
+        }
+        next.due_date = Some(rec.next_after(base)?.format("%Y-%m-%d").to_string());
+        Some(next)
+    }
+
+    fn due_date(&self) -> Option<Date<Utc>> {
+        utility::parse_ymd(self.due_date.as_ref()?)
+    }
+
+    /// Date from the `t:` key, before which the task isn't actionable
+    pub fn threshold_date(&self) -> Option<Date<Utc>> {
+        utility::parse_ymd(self.extension("t")?)
+    }
+
+    pub fn is_actionable(&self) -> bool {
+        match self.threshold_date() {
+            Some(threshold) => threshold <= utility::date_today(),
+            None => true,
+        }
     }
 
     pub fn mark_undone(&mut self) {
@@ -175,12 +469,13 @@ impl Todo {
         utility::notify("UNDONE", &self);
     }
 
-    pub fn schedule(&mut self, date: &str) {
+    pub fn schedule(&mut self, date: &str) -> super::Result<()> {
         self.due_date = Some(utility::parse_date_string_relative(
             utility::date_today(),
             date,
-        ));
+        )?);
         utility::notify("SCHEDULED", &self);
+        Ok(())
     }
 
     pub fn unschedule(&mut self) {
@@ -221,17 +516,54 @@ impl Todo {
         }
     }
 
-    // display [x DONEDATE | PRIORITY] TEXT [DUEDATE] +TAGS @tagS
+    /// Days since the task was created, if it has a creation date
+    pub fn age_in_days(&self) -> Option<i64> {
+        let created = utility::parse_ymd(self.created_date.as_ref()?)?;
+        Some((utility::date_today() - created).num_days())
+    }
+
+    // display [x DONEDATE | PRIORITY] [CREATEDDATE] TEXT [DUEDATE] +TAGS @tagS
+    //
+    // Tasks read from a file keep their original layout: unmodified tasks are
+    // written back byte-for-byte, and modified ones keep their token order.
     pub fn format_for_save(&self) -> String {
+        let source = match &self.source.0 {
+            Some(source) => source,
+            None => return self.format_canonical(),
+        };
+        let mut original = match source.parse::<Todo>() {
+            Ok(original) => original,
+            Err(_) => return self.format_canonical(),
+        };
+        original.idx = self.idx;
+        if original == *self {
+            return source.clone();
+        }
+        self.format_like(&original, source)
+    }
+
+    fn prefix_string(&self) -> String {
         utility::join_non_empty(
             [
-                &self.done_or_priority_string(),
+                self.done_or_priority_string().as_str(),
+                self.created_date.as_deref().unwrap_or_default(),
+            ]
+            .iter(),
+        )
+    }
+
+    fn format_canonical(&self) -> String {
+        utility::join_non_empty(
+            [
+                &self.prefix_string(),
                 &self.task,
                 &self
                     .due_date
                     .as_ref()
                     .map(|x| format!("due:{}", x))
                     .unwrap_or_default(),
+                &self.rec.map(|x| format!("rec:{}", x)).unwrap_or_default(),
+                &self.extensions_string(),
                 &self.projects.join(" "),
                 &self.tags.join(" "),
             ]
@@ -239,6 +571,85 @@ impl Todo {
         )
     }
 
+    /// Lay out a modified task using the token order of the line it came from.
+    fn format_like(&self, original: &Todo, source: &str) -> String {
+        let tokens = tokenise(source);
+        let n_words = tokens
+            .iter()
+            .filter(|t| matches!(t, Token::Word(_)))
+            .count();
+        // Text added before or after the original words keeps them in place,
+        // as long as it doesn't run into them; otherwise all of it is replaced
+        let (text_before, text_after, keep_original_text) = if original.task.is_empty() {
+            (String::new(), self.task.clone(), true)
+        } else if self.task == original.task {
+            (String::new(), String::new(), true)
+        } else if let Some(suffix) = self
+            .task
+            .strip_prefix(&original.task)
+            .filter(|s| s.starts_with(char::is_whitespace))
+        {
+            (String::new(), suffix.trim().to_string(), true)
+        } else if let Some(prefix) = self
+            .task
+            .strip_suffix(&original.task)
+            .filter(|s| s.ends_with(char::is_whitespace))
+        {
+            (prefix.trim().to_string(), String::new(), true)
+        } else {
+            (self.task.clone(), String::new(), false)
+        };
+
+        let mut due = self.due_date.as_ref().map(|x| format!("due:{}", x));
+        let mut rec = self.rec.map(|x| format!("rec:{}", x));
+        let mut extensions = self.extensions.clone();
+        let mut projects = self.projects.clone();
+        let mut tags = self.tags.clone();
+        let take = |remaining: &mut Vec<String>, token: &str| {
+            let pos = remaining.iter().position(|x| x == token)?;
+            Some(remaining.remove(pos))
+        };
+
+        let mut parts = vec![self.prefix_string()];
+        let mut words_seen = 0;
+        for token in tokens {
+            match token {
+                Token::Done(_) | Token::Priority(_) | Token::Created(_) => {}
+                Token::Word(word) => {
+                    if words_seen == 0 {
+                        parts.push(text_before.clone());
+                    }
+                    if keep_original_text {
+                        parts.push(word.to_string());
+                    }
+                    words_seen += 1;
+                    if words_seen == n_words {
+                        parts.push(text_after.clone());
+                    }
+                }
+                Token::Due(_) => parts.extend(due.take()),
+                Token::Rec(_) => parts.extend(rec.take()),
+                Token::Extension(key, _) => {
+                    if let Some(pos) = extensions.iter().position(|(k, _)| k == key) {
+                        let (k, v) = extensions.remove(pos);
+                        parts.push(format!("{}:{}", k, v));
+                    }
+                }
+                Token::Project(project) => parts.extend(take(&mut projects, project)),
+                Token::Tag(tag) => parts.extend(take(&mut tags, tag)),
+            }
+        }
+        if n_words == 0 {
+            parts.push(text_after);
+        }
+        parts.extend(due);
+        parts.extend(rec);
+        parts.extend(extensions.iter().map(|(k, v)| format!("{}:{}", k, v)));
+        parts.extend(projects);
+        parts.extend(tags);
+        utility::join_non_empty(parts.iter())
+    }
+
     // display TEXT +TAGS @tagS
     pub fn donesummary_format(&self) -> String {
         utility::join_non_empty([&self.task, &self.projects.join(" "), &self.tags.join(" ")].iter())
@@ -255,43 +666,214 @@ impl Todo {
     }
 }
 
+/// A task as structured data, for `--format json` and `import`/`export`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Record {
+    pub idx: usize,
+    pub done: bool,
+    pub priority: Option<char>,
+    pub text: String,
+    pub projects: Vec<String>,
+    pub tags: Vec<String>,
+    pub created: Option<String>,
+    pub due: Option<String>,
+    pub completed: Option<String>,
+    pub rec: Option<String>,
+    pub extensions: BTreeMap<String, String>,
+}
+
+impl From<&Todo> for Record {
+    fn from(todo: &Todo) -> Record {
+        Record {
+            idx: todo.idx,
+            done: todo.done_date.is_some(),
+            priority: todo.pri.letter(),
+            text: todo.task.clone(),
+            projects: todo.projects.clone(),
+            tags: todo.tags.clone(),
+            created: todo.created_date.clone(),
+            due: todo.due_date.clone(),
+            completed: todo.done_date.clone(),
+            rec: todo.rec.map(|r| r.to_string()),
+            extensions: todo.extensions.iter().cloned().collect(),
+        }
+    }
+}
+
+impl TryFrom<Record> for Todo {
+    type Error = anyhow::Error;
+    fn try_from(record: Record) -> Result<Todo, Self::Error> {
+        let check_date = |date: Option<String>| match date {
+            Some(d) if utility::parse_ymd(&d).is_none() => {
+                Err(anyhow::anyhow!("Expected a YYYY-MM-DD date, got `{}`", d))
+            }
+            d => Ok(d),
+        };
+        let with_sigil = |sigil: char, names: Vec<String>| -> Vec<String> {
+            names
+                .into_iter()
+                .map(|n| match n.starts_with(sigil) {
+                    true => n,
+                    false => format!("{}{}", sigil, n),
+                })
+                .collect()
+        };
+        let completed = check_date(record.completed)?;
+        let done_date = match (record.done, completed) {
+            (true, None) => Some(utility::date_today().format("%Y-%m-%d").to_string()),
+            (true, completed) => completed,
+            (false, _) => None,
+        };
+        Ok(Todo {
+            idx: record.idx,
+            task: record.text,
+            pri: match record.priority {
+                Some(p) => p.to_string().parse()?,
+                None => TodoPriority::None,
+            },
+            projects: with_sigil('+', record.projects),
+            tags: with_sigil('@', record.tags),
+            done_date,
+            created_date: check_date(record.created)?,
+            due_date: check_date(record.due)?,
+            rec: record.rec.map(|r| r.parse()).transpose()?,
+            extensions: record.extensions.into_iter().collect(),
+            source: SourceLine::default(),
+        })
+    }
+}
+
+/// Split a project or context into its hierarchy, keeping the separators,
+/// so `+work.client/billing` becomes `+work`, `.client`, and `/billing`.
+pub fn hierarchy(project: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (i, c) in project.char_indices().skip(1) {
+        if c == '.' || c == '/' {
+            parts.push(&project[start..i]);
+            start = i;
+        }
+    }
+    parts.push(&project[start..]);
+    parts
+}
+
+/// Whether `project` is `parent`, or nested anywhere beneath it (ignoring case)
+pub fn is_within(project: &str, parent: &str) -> bool {
+    let (project, parent) = (project.to_lowercase(), parent.to_lowercase());
+    match project.strip_prefix(&parent) {
+        Some(rest) => rest.is_empty() || rest.starts_with('.') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Split a `key:value` token, ignoring URLs such as `https://...`.
+///
+/// The value may be empty, so that filters like `key:` can be recognised.
+pub fn split_key_value(token: &str) -> Option<(&str, &str)> {
+    let (key, value) = token.split_once(':')?;
+    let valid_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid_key && !value.starts_with("//") {
+        Some((key, value))
+    } else {
+        None
+    }
+}
+
+/// A single whitespace-separated piece of a todo.txt line
+enum Token<'a> {
+    Done(&'a str),
+    Priority(TodoPriority),
+    Created(&'a str),
+    Due(&'a str),
+    Rec(Recurrence),
+    Extension(&'a str, &'a str),
+    Project(&'a str),
+    Tag(&'a str),
+    Word(&'a str),
+}
+
+fn tokenise(s: &str) -> Vec<Token<'_>> {
+    let token_iter: Vec<&str> = s.split_whitespace().collect();
+    let is_priority = |word: &str| {
+        word.starts_with('(')
+            && word.ends_with(')')
+            && word.len() == 3
+            && word[1..2].parse::<TodoPriority>().is_ok()
+    };
+    let is_date = |word: &str| utility::parse_ymd(word).is_some();
+    // The creation date may only follow the done marker or priority
+    let mut created_date_idx = 0;
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    loop {
+        let token = match token_iter.get(idx) {
+            None => break,
+            Some(token) => *token,
+        };
+
+        if idx == 0 && token == "x" && token_iter.len() > 1 {
+            tokens.push(Token::Done(token_iter[1]));
+            idx = 2;
+            created_date_idx = 2;
+            continue;
+        } else if is_priority(token) {
+            tokens.push(Token::Priority(token[1..2].parse().unwrap_or_default()));
+            if idx == created_date_idx {
+                created_date_idx += 1;
+            }
+        } else if idx == created_date_idx && is_date(token) {
+            tokens.push(Token::Created(token));
+        } else if let Some(date) = token.strip_prefix("due:") {
+            tokens.push(Token::Due(date));
+        } else if let Some(r) = token.strip_prefix("rec:").and_then(|r| r.parse().ok()) {
+            tokens.push(Token::Rec(r));
+        } else if let Some((key, value)) = split_key_value(token).filter(|(_, v)| !v.is_empty()) {
+            tokens.push(Token::Extension(key, value));
+        } else if token.starts_with('@') {
+            tokens.push(Token::Tag(token));
+        } else if token.starts_with('+') {
+            tokens.push(Token::Project(token));
+        } else {
+            tokens.push(Token::Word(token));
+        }
+        idx += 1;
+    }
+    tokens
+}
+
 // Implement .parse() for Todo
 impl FromStr for Todo {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut done_date = None;
+        let mut created_date = None;
         let mut priority = TodoPriority::None;
         let mut task_parts = Vec::new();
         let mut projects = Vec::new();
         let mut tags = Vec::new();
         let mut due_date = None;
+        let mut rec = None;
+        let mut extensions = Vec::new();
 
-        let token_iter: Vec<&str> = s.split_whitespace().collect();
-        let is_priority =
-            |word: &str| word.starts_with('(') && word.ends_with(')') && word.len() == 3;
-        let mut idx = 0;
-        loop {
-            let token = match token_iter.get(idx) {
-                None => break,
-                Some(token) => *token,
-            };
-
-            if idx == 0 && token == "x" {
-                done_date = Some(token_iter[1].to_string());
-                idx = 2;
-                continue;
-            } else if is_priority(token) {
-                priority = token[1..2].parse().unwrap_or_default()
-            } else if let Some(date) = token.strip_prefix("due:") {
-                due_date = Some(date.to_string());
-            } else if token.starts_with('@') {
-                tags.push(token);
-            } else if token.starts_with('+') {
-                projects.push(token);
-            } else {
-                task_parts.push(token);
+        for token in tokenise(s) {
+            match token {
+                Token::Done(date) => done_date = Some(date.to_string()),
+                Token::Priority(pri) => priority = pri,
+                Token::Created(date) => created_date = Some(date.to_string()),
+                Token::Due(date) => due_date = Some(date.to_string()),
+                Token::Rec(r) => rec = Some(r),
+                Token::Extension(key, value) => {
+                    extensions.push((key.to_string(), value.to_string()))
+                }
+                Token::Project(project) => projects.push(project),
+                Token::Tag(tag) => tags.push(tag),
+                Token::Word(word) => task_parts.push(word),
             }
-            idx += 1;
         }
         Ok(Todo {
             idx: 0,
@@ -300,7 +882,11 @@ impl FromStr for Todo {
             projects: projects.iter().map(|x| x.to_string()).collect(),
             tags: tags.iter().map(|x| x.to_string()).collect(),
             done_date,
+            created_date,
             due_date,
+            rec,
+            extensions,
+            source: SourceLine(Some(s.to_string())),
         })
     }
 }
@@ -314,14 +900,20 @@ impl Display for Todo {
                 &self
                     .due_date
                     .as_ref()
-                    .map(|x| format!("due:{}", x))
+                    .map(|x| format!("due:{}", utility::display_date(x)))
                     .unwrap_or_default(),
+                &self.rec.map(|x| format!("rec:{}", x)).unwrap_or_default(),
+                &self.extensions_string(),
             ]
             .iter(),
         );
         let mut post_parts = self.projects.clone();
         post_parts.extend(self.tags.clone());
         let post = utility::join_non_empty(post_parts.iter());
+        let age = match (&self.done_date, self.age_in_days()) {
+            (None, Some(age)) => format!("[{}d]", age),
+            _ => String::new(),
+        };
 
         let colourer = match self.pri {
             TodoPriority::A => colour::yellow,
@@ -346,6 +938,7 @@ impl Display for Todo {
             format!("{:3}", self.idx),
             pre.trim().to_string(),
             post.trim().to_string(),
+            age,
         ]
         .iter()
         .filter(|x| !x.is_empty())
@@ -358,8 +951,10 @@ impl Display for Todo {
 
 #[cfg(test)]
 mod tests {
+    use chrono::{TimeZone, Utc};
+
     use crate::{
-        todo::{Todo, TodoPriority},
+        todo::{hierarchy, Record, Recurrence, RecurrenceUnit, SourceLine, Todo, TodoPriority},
         utility::date_today,
     };
 
@@ -373,9 +968,13 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
-        assert_eq!(format!("  0. {}", input), t.to_string());
+        assert_eq!(format!("  0 {}", input), t.to_string());
     }
 
     #[test]
@@ -388,7 +987,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         let got: Todo = input.parse().unwrap();
         assert_eq!(t, got);
@@ -404,12 +1007,115 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: Some("2021-01-01".to_string()),
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         let got: Todo = input.parse().unwrap();
         assert_eq!(t, got);
     }
 
+    #[test]
+    fn can_parse_recurring_task() {
+        let input = "this is a test due:2021-01-01 rec:+1w +p1 @c1";
+        let got: Todo = input.parse().unwrap();
+        assert_eq!(
+            got.rec,
+            Some(Recurrence {
+                strict: true,
+                n: 1,
+                unit: RecurrenceUnit::Week
+            })
+        );
+        assert_eq!(got.task, "this is a test");
+        assert_eq!(got.format_for_save(), input);
+
+        let got: Todo = "this is a test rec:3d".parse().unwrap();
+        assert_eq!(got.rec.map(|r| r.to_string()), Some("3d".to_string()));
+
+        let got: Todo = "this is a test rec:often".parse().unwrap();
+        assert_eq!(got.rec, None);
+        assert_eq!(got.format_for_save(), "this is a test rec:often");
+
+        let mut got: Todo = "this is a test rec:+9999999m".parse().unwrap();
+        assert_eq!(got.rec, None);
+        assert_eq!(got.mark_done(), None);
+    }
+
+    #[test]
+    fn can_parse_extension_tags() {
+        let input = "this is a test id:42 est:2h see https://example.com +p1";
+        let got: Todo = input.parse().unwrap();
+        assert_eq!(got.task, "this is a test see https://example.com");
+        assert_eq!(
+            got.extensions,
+            vec![
+                ("id".to_string(), "42".to_string()),
+                ("est".to_string(), "2h".to_string())
+            ]
+        );
+        assert_eq!(got.format_for_save(), input);
+    }
+
+    #[test]
+    fn can_match_extension_tags() {
+        let t: Todo = "this is a test id:42 due:2021-01-01".parse().unwrap();
+        assert!(t.matches_term("id:42") && !t.matches_term("est:"));
+        assert!(t.matches_term("id:") && t.matches_term("due:2021-01-01"));
+        assert!(!t.matches_term("id:43"));
+    }
+
+    #[test]
+    fn future_threshold_is_not_actionable() {
+        let t: Todo = "this is a test t:2021-09-14".parse().unwrap();
+        assert_eq!(t.threshold_date(), Some(Utc.ymd(2021, 9, 14)));
+        assert!(!t.is_actionable());
+
+        let t: Todo = "this is a test t:2021-09-13".parse().unwrap();
+        assert!(t.is_actionable());
+
+        let t: Todo = "this is a test".parse().unwrap();
+        assert!(t.is_actionable());
+    }
+
+    #[test]
+    fn marking_recurring_task_done_spawns_next() {
+        let mut strict: Todo = "water plants due:2021-09-01 rec:+1m".parse().unwrap();
+        let next = strict.mark_done().unwrap();
+        assert_eq!(next.due_date, Some("2021-10-01".to_string()));
+        assert_eq!(next.done_date, None);
+
+        let mut lenient: Todo = "(A) water plants due:2021-09-01 rec:2w".parse().unwrap();
+        let next = lenient.mark_done().unwrap();
+        assert_eq!(next.due_date, Some("2021-09-27".to_string()));
+        assert_eq!(next.pri, TodoPriority::A);
+
+        let mut once: Todo = "water plants due:2021-09-01".parse().unwrap();
+        assert_eq!(once.mark_done(), None);
+    }
+
+    #[test]
+    fn can_parse_creation_date() {
+        let got: Todo = "(A) 2021-09-01 this is a test +p1".parse().unwrap();
+        assert_eq!(got.pri, TodoPriority::A);
+        assert_eq!(got.created_date, Some("2021-09-01".to_string()));
+        assert_eq!(got.task, "this is a test");
+        assert_eq!(got.age_in_days(), Some(12));
+        assert_eq!(got.to_string(), "  0 (A) this is a test +p1 [12d]");
+        assert_eq!(got.format_for_save(), "(A) 2021-09-01 this is a test +p1");
+
+        let input = "x 2021-09-10 2021-09-01 this is a test";
+        let got: Todo = input.parse().unwrap();
+        assert_eq!(got.done_date, Some("2021-09-10".to_string()));
+        assert_eq!(got.created_date, Some("2021-09-01".to_string()));
+        assert_eq!(got.format_for_save(), input);
+
+        let got: Todo = "this is a test 2021-09-01".parse().unwrap();
+        assert_eq!(got.created_date, None);
+    }
+
     #[test]
     fn can_append_text_to_tast() {
         let mut t = Todo {
@@ -419,7 +1125,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         t.append_text("EXTRA");
 
@@ -430,12 +1140,41 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
 
         assert_eq!(t, expected);
     }
 
+    #[test]
+    fn replacing_text_keeps_dates_and_metadata() {
+        let mut t: Todo = "(A) 2021-09-01 call bob due:2021-09-20 id:4 +work @phone"
+            .parse()
+            .unwrap();
+        assert_eq!(t.description(), "call bob due:2021-09-20 id:4 +work @phone");
+
+        t.replace_text("email alice +home").unwrap();
+        assert_eq!(
+            t.format_for_save(),
+            "(A) 2021-09-01 email alice due:2021-09-20 id:4 +home"
+        );
+        t.replace_text("email alice due:2021-10-01").unwrap();
+        assert_eq!(t.due_date.as_deref(), Some("2021-10-01"));
+        assert!(t.replace_text("email alice due:later").is_err());
+
+        let mut t: Todo = "call bob id:2".parse().unwrap();
+        t.replace_text("call bobby").unwrap();
+        assert_eq!(t.format_for_save(), "call bobby id:2");
+        t.replace_text("recall bobby").unwrap();
+        assert_eq!(t.format_for_save(), "recall bobby id:2");
+        t.replace_text("recall bobby now").unwrap();
+        assert_eq!(t.format_for_save(), "recall bobby now id:2");
+    }
+
     #[test]
     fn can_prepend_text_to_task() {
         let mut t = Todo {
@@ -445,7 +1184,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         t.prepend_text("EXTRA");
 
@@ -456,7 +1199,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
 
         assert_eq!(t, expected);
@@ -471,9 +1218,13 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
-        t.schedule("today");
+        t.schedule("today").unwrap();
 
         let expected = Todo {
             idx: 0,
@@ -482,7 +1233,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some(date_today().format("%F").to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
 
         assert_eq!(t, expected);
@@ -497,7 +1252,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         t.unschedule();
 
@@ -508,7 +1267,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
 
         assert_eq!(t, expected);
@@ -523,12 +1286,32 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
 
-        assert!(t.matches(&["test"], &["blah"]));
-        assert!(!t.matches(&["test"], &["+p1"]));
-        assert!(t.matches(&["test"], &["+badproj"]));
+        assert!(t.matches_term("test") && !t.matches_term("blah"));
+        assert!(t.matches_term("+p1"));
+        assert!(!t.matches_term("+badproj"));
+        assert!(!t.matches_term("+p"));
+        assert!(t.matches_term("@C1"));
+    }
+
+    #[test]
+    fn can_match_hierarchical_projects() {
+        let t: Todo = "send invoice +work.clientA/billing".parse().unwrap();
+        assert!(t.matches_term("+work"));
+        assert!(t.matches_term("+work.clientA"));
+        assert!(t.matches_term("+work.clienta/billing"));
+        assert!(!t.matches_term("+work.client"));
+        assert!(!t.matches_term("+wor"));
+        assert_eq!(
+            hierarchy("+work.clientA/billing"),
+            vec!["+work", ".clientA", "/billing"]
+        );
     }
 
     #[test]
@@ -540,7 +1323,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some(date_today().format("%F").to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         input.mark_done();
         let want = Todo {
@@ -550,7 +1337,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: Some(date_today().format("%F").to_string()),
+            created_date: None,
             due_date: Some(date_today().format("%F").to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(input, want);
 
@@ -561,7 +1352,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some(date_today().format("%F").to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         input.mark_done();
         let want = Todo {
@@ -571,7 +1366,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: Some(date_today().format("%F").to_string()),
+            created_date: None,
             due_date: Some(date_today().format("%F").to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(input, want);
     }
@@ -585,7 +1384,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: Some(date_today().format("%F").to_string()),
+            created_date: None,
             due_date: Some(date_today().format("%F").to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         input.mark_undone();
         let want = Todo {
@@ -595,7 +1398,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some(date_today().format("%F").to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(input, want);
     }
@@ -609,7 +1416,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         input.prioritise(TodoPriority::A);
         let want = Todo {
@@ -619,11 +1430,26 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(input, want);
     }
 
+    #[test]
+    fn can_raise_and_lower_priority() {
+        assert_eq!(TodoPriority::C.raised(), TodoPriority::B);
+        assert_eq!(TodoPriority::A.raised(), TodoPriority::A);
+        assert_eq!(TodoPriority::None.raised(), TodoPriority::Z);
+        assert_eq!(TodoPriority::C.lowered(), TodoPriority::D);
+        assert_eq!(TodoPriority::Z.lowered(), TodoPriority::None);
+        assert_eq!(TodoPriority::None.lowered(), TodoPriority::None);
+        assert!("1".parse::<TodoPriority>().is_err());
+    }
+
     #[test]
     fn can_remove_task_priority() {
         let mut input = Todo {
@@ -633,7 +1459,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         input.prioritise(TodoPriority::None);
         let want = Todo {
@@ -643,7 +1473,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(input, want);
     }
@@ -657,7 +1491,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: None,
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(
             input.format_for_save(),
@@ -671,7 +1509,11 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: Some("2021-01-01".to_string()),
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(
             input.format_for_save(),
@@ -685,7 +1527,11 @@ mod tests {
             projects: vec![],
             tags: vec!["@c1".to_string()],
             done_date: Some("2021-01-01".to_string()),
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(
             input.format_for_save(),
@@ -693,6 +1539,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unmodified_lines_round_trip_exactly() {
+        let prefixes = [
+            "",
+            "(A) ",
+            "(B) 2021-09-01 ",
+            "2021-09-01 ",
+            "x 2021-09-10 ",
+            "x 2021-09-10 2021-09-01 ",
+        ];
+        let bodies = [
+            "call +bob about @home the report",
+            "call  +bob about\t@home   the report",
+            "+proj at the start due:2021-01-01 then words",
+            "@ctx only",
+            "rec:+1w first then t:2021-09-20 threshold id:7",
+            "see [docs](https://example.com) and https://example.org",
+            "(C) priority in the middle +a +a @b",
+            "x marks the spot",
+            "trailing space ",
+            "",
+        ];
+        for prefix in prefixes.iter() {
+            for body in bodies.iter() {
+                let line = format!("{}{}", prefix, body);
+                let todo: Todo = line.parse().unwrap();
+                assert_eq!(todo.format_for_save(), line);
+                for (i, word) in line.split(' ').enumerate() {
+                    let reordered = format!("{} {}", word, line.replacen(word, "", 1));
+                    let todo: Todo = reordered.parse().unwrap();
+                    assert_eq!(todo.format_for_save(), reordered, "token {}", i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn modified_lines_keep_token_order() {
+        let mut t: Todo = "call +bob about @home the report".parse().unwrap();
+        t.schedule("today").unwrap();
+        assert_eq!(
+            t.format_for_save(),
+            "call +bob about @home the report due:2021-09-13"
+        );
+
+        t.append_text("tonight");
+        t.prioritise(TodoPriority::B);
+        assert_eq!(
+            t.format_for_save(),
+            "(B) call +bob about @home the report tonight due:2021-09-13"
+        );
+
+        let mut t: Todo = "due:2021-01-01 call +bob about it id:1".parse().unwrap();
+        t.prepend_text("please");
+        t.unschedule();
+        t.mark_done();
+        assert_eq!(
+            t.format_for_save(),
+            "x 2021-09-13 please call +bob about it id:1"
+        );
+    }
+
     #[test]
     fn can_format_todo_output() {
         let input = Todo {
@@ -702,11 +1610,32 @@ mod tests {
             projects: vec!["+p1".to_string(), "+p2".to_string()],
             tags: vec!["@c1".to_string()],
             done_date: Some("2021-01-01".to_string()),
+            created_date: None,
             due_date: Some("2021-01-01".to_string()),
+            rec: None,
+            extensions: vec![],
+            source: SourceLine::default(),
         };
         assert_eq!(
-            "  0. x 2021-01-01 this is a test due:2021-01-01 +p1 +p2 @c1",
+            "  0 x 2021-01-01 this is a test due:2021-01-01 +p1 +p2 @c1",
             input.to_string(),
         );
     }
+
+    #[test]
+    fn can_make_records() {
+        let t: Todo = "(B) 2021-09-01 call bob due:2021-09-20 rec:+1w id:7 +work @phone"
+            .parse()
+            .unwrap();
+        let record = Record::from(&t);
+        assert_eq!(record.priority, Some('B'));
+        assert_eq!(record.text, "call bob");
+        assert_eq!(record.projects, vec!["+work"]);
+        assert_eq!(record.tags, vec!["@phone"]);
+        assert_eq!(record.created.as_deref(), Some("2021-09-01"));
+        assert_eq!(record.due.as_deref(), Some("2021-09-20"));
+        assert_eq!(record.rec.as_deref(), Some("+1w"));
+        assert_eq!(record.extensions.get("id").map(String::as_str), Some("7"));
+        assert!(!record.done);
+    }
 }
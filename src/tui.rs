@@ -0,0 +1,307 @@
+use super::{modify, todo::Todo, utility};
+
+use anyhow::anyhow;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use std::io::{self, IsTerminal, Write};
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
+
+const HELP: &str = "j/k move  / filter  x do  p priority  s schedule  a append  r remove  q quit";
+
+/// What a line of typed text will be used for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prompt {
+    Priority,
+    Schedule,
+    Append,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Normal,
+    Filter,
+    Prompt(Prompt, String),
+    ConfirmRemove,
+}
+
+/// Interactive triage of the tasks in `todos`, which are changed in place
+/// and saved by `main` as for any other command.
+pub fn run(todos: &mut Vec<Todo>, dones: &[Todo], filters: &[String]) -> Result<()> {
+    let mut app = App::new(filters);
+    with_terminal(|out| app.event_loop(out, todos, dones))
+}
+
+/// Run `f` full screen, putting the terminal back afterwards even if it fails
+pub fn with_terminal<T>(f: impl FnOnce(&mut io::Stdout) -> Result<T>) -> Result<T> {
+    if !io::stdout().is_terminal() {
+        return Err(anyhow!("This needs a terminal").into());
+    }
+    // Selection is shown by inverting the line, which clashes with colour
+    colored::control::set_override(false);
+    terminal::enable_raw_mode()?;
+    let mut out = io::stdout();
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let result = f(&mut out);
+    execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    colored::control::unset_override();
+    result
+}
+
+struct App {
+    filter: String,
+    /// Position of the highlighted task among those shown
+    selected: usize,
+    mode: Mode,
+    status: String,
+    quit: bool,
+}
+
+impl App {
+    fn new(filters: &[String]) -> App {
+        App {
+            filter: filters.join(" "),
+            selected: 0,
+            mode: Mode::Normal,
+            status: String::new(),
+            quit: false,
+        }
+    }
+
+    fn event_loop(
+        &mut self,
+        out: &mut impl Write,
+        todos: &mut Vec<Todo>,
+        dones: &[Todo],
+    ) -> Result<()> {
+        while !self.quit {
+            let shown = self.shown(todos);
+            self.draw(out, todos, &shown)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.handle(key, todos, dones);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Indices of the tasks matching the filter, or of every task while the
+    /// filter is only partly typed
+    fn shown(&self, todos: &[Todo]) -> Vec<usize> {
+        let filters: Vec<String> = self.filter.split_whitespace().map(String::from).collect();
+        match utility::todo_filter(todos.iter(), &filters) {
+            Ok(matching) => matching.map(|t| t.idx).collect(),
+            Err(_) => (0..todos.len()).collect(),
+        }
+    }
+
+    fn handle(&mut self, key: KeyEvent, todos: &mut Vec<Todo>, dones: &[Todo]) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        let shown = self.shown(todos);
+        self.selected = self.selected.min(shown.len().saturating_sub(1));
+        let current = shown.get(self.selected).copied();
+        match (&mut self.mode, key.code) {
+            (Mode::Normal, KeyCode::Char('q')) | (Mode::Normal, KeyCode::Esc) => self.quit = true,
+            (Mode::Normal, KeyCode::Char('j')) | (Mode::Normal, KeyCode::Down) => {
+                self.selected = (self.selected + 1).min(shown.len().saturating_sub(1))
+            }
+            (Mode::Normal, KeyCode::Char('k')) | (Mode::Normal, KeyCode::Up) => {
+                self.selected = self.selected.saturating_sub(1)
+            }
+            (Mode::Normal, KeyCode::Char('g')) | (Mode::Normal, KeyCode::Home) => self.selected = 0,
+            (Mode::Normal, KeyCode::Char('G')) | (Mode::Normal, KeyCode::End) => {
+                self.selected = shown.len().saturating_sub(1)
+            }
+            (Mode::Normal, KeyCode::Char('/')) => self.mode = Mode::Filter,
+            (Mode::Normal, _) if current.is_none() => (),
+            (Mode::Normal, KeyCode::Char('x')) => {
+                let idx = current.unwrap();
+                self.report(modify::do_task(&[idx], todos, dones), "Done", todos, idx);
+            }
+            (Mode::Normal, KeyCode::Char('p')) => {
+                self.mode = Mode::Prompt(Prompt::Priority, String::new())
+            }
+            (Mode::Normal, KeyCode::Char('s')) => {
+                self.mode = Mode::Prompt(Prompt::Schedule, String::new())
+            }
+            (Mode::Normal, KeyCode::Char('a')) => {
+                self.mode = Mode::Prompt(Prompt::Append, String::new())
+            }
+            (Mode::Normal, KeyCode::Char('r')) => self.mode = Mode::ConfirmRemove,
+            (Mode::Normal, _) => (),
+
+            (Mode::Filter, KeyCode::Char(c)) => {
+                self.filter.push(c);
+                self.selected = 0;
+            }
+            (Mode::Filter, KeyCode::Backspace) => {
+                self.filter.pop();
+                self.selected = 0;
+            }
+            (Mode::Filter, KeyCode::Enter) => self.mode = Mode::Normal,
+            (Mode::Filter, KeyCode::Esc) => {
+                self.filter.clear();
+                self.mode = Mode::Normal;
+            }
+            (Mode::Filter, _) => (),
+
+            (Mode::Prompt(_, text), KeyCode::Char(c)) => text.push(c),
+            (Mode::Prompt(_, text), KeyCode::Backspace) => {
+                text.pop();
+            }
+            (Mode::Prompt(..), KeyCode::Esc) => self.mode = Mode::Normal,
+            (Mode::Prompt(prompt, text), KeyCode::Enter) => {
+                let (prompt, text) = (*prompt, text.trim().to_string());
+                self.mode = Mode::Normal;
+                if let Some(idx) = current {
+                    self.apply(prompt, &text, todos, idx);
+                }
+            }
+            (Mode::Prompt(..), _) => (),
+
+            (Mode::ConfirmRemove, KeyCode::Char('y')) => {
+                self.mode = Mode::Normal;
+                if let Some(idx) = current {
+                    let task = todos[idx].task.clone();
+                    match modify::remove(&[idx], todos) {
+                        Ok(()) => self.status = format!("Removed: {}", task),
+                        Err(e) => self.status = e.to_string(),
+                    }
+                    renumber(todos);
+                }
+            }
+            (Mode::ConfirmRemove, _) => self.mode = Mode::Normal,
+        }
+    }
+
+    fn apply(&mut self, prompt: Prompt, text: &str, todos: &mut [Todo], idx: usize) {
+        let (result, verb) = match prompt {
+            Prompt::Priority if text.is_empty() => {
+                (modify::prioritise(idx, todos, None), "Deprioritised")
+            }
+            Prompt::Priority => (
+                modify::prioritise(idx, todos, Some(text.to_uppercase())),
+                "Prioritised",
+            ),
+            Prompt::Schedule if text.is_empty() => {
+                (modify::unschedule_each(&[idx], todos), "Unscheduled")
+            }
+            Prompt::Schedule => (modify::schedule(idx, todos, text), "Scheduled"),
+            Prompt::Append => (modify::append(idx, todos, text), "Appended"),
+        };
+        self.report(result, verb, todos, idx);
+    }
+
+    fn report(&mut self, result: Result<()>, verb: &str, todos: &[Todo], idx: usize) {
+        self.status = match result {
+            Ok(()) => format!("{}: {}", verb, todos[idx].task),
+            Err(e) => e.to_string(),
+        };
+    }
+
+    fn draw(&self, out: &mut impl Write, todos: &[Todo], shown: &[usize]) -> Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, rows) = (width as usize, (height as usize).saturating_sub(2));
+        let selected = self.selected.min(shown.len().saturating_sub(1));
+        let top = (selected + 1).saturating_sub(rows);
+
+        queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        let header = format!(
+            "{} of {} tasks  filter: {}",
+            shown.len(),
+            todos.len(),
+            self.filter
+        );
+        queue!(out, Print(truncate(&header, width)))?;
+        for (row, &idx) in shown.iter().enumerate().skip(top).take(rows) {
+            let line = truncate(&todos[idx].to_string(), width);
+            queue!(out, cursor::MoveTo(0, (row - top + 1) as u16))?;
+            if row == selected {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(format!("{:width$}", line, width = width)),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+        }
+
+        let footer = match &self.mode {
+            Mode::Normal if self.status.is_empty() => HELP.to_string(),
+            Mode::Normal => self.status.clone(),
+            Mode::Filter => format!("/{}", self.filter),
+            Mode::Prompt(Prompt::Priority, text) => format!("Priority (blank to remove): {}", text),
+            Mode::Prompt(Prompt::Schedule, text) => format!("Due date (blank to remove): {}", text),
+            Mode::Prompt(Prompt::Append, text) => format!("Append: {}", text),
+            Mode::ConfirmRemove => "Remove this task? (y/n)".to_string(),
+        };
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1)),
+            Print(truncate(&footer, width))
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Keep each task's index equal to its line, as it will be once saved
+fn renumber(todos: &mut [Todo]) {
+    todos.iter_mut().enumerate().for_each(|(i, t)| t.idx = i);
+}
+
+pub fn truncate(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(app: &mut App, todos: &mut Vec<Todo>, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                c => KeyCode::Char(c),
+            };
+            app.handle(KeyEvent::from(code), todos, &[]);
+        }
+    }
+
+    #[test]
+    fn keys_act_on_the_selected_task() {
+        let mut todos = utility::parse_todos("call bob +work\nbuy milk @shop\nwrite report +work");
+        let mut app = App::new(&["+work".to_string()]);
+        assert_eq!(app.shown(&todos), vec![0, 2]);
+
+        press(&mut app, &mut todos, "jpb\n");
+        assert_eq!(todos[2].format_for_save(), "(B) write report +work");
+
+        press(&mut app, &mut todos, "snot a date\n");
+        assert!(app.status.starts_with("Couldn't understand"));
+        assert_eq!(todos[2].due_date, None);
+
+        press(&mut app, &mut todos, "/\x1bry");
+        let left: Vec<String> = todos.iter().map(|t| t.format_for_save()).collect();
+        assert_eq!(left, vec!["call bob +work", "(B) write report +work"]);
+        assert_eq!(todos[1].idx, 1);
+
+        press(&mut app, &mut todos, "x");
+        assert!(todos[1].done_date.is_some());
+        press(&mut app, &mut todos, "q");
+        assert!(app.quit);
+    }
+}
@@ -1,77 +1,289 @@
 use anyhow::anyhow;
-use std::env;
+use std::convert::TryFrom;
 use std::fmt::Display;
 use std::fs;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use super::todo::Todo;
+use super::{config, query::Query, todo::Todo};
 
-use chrono::{Date, Duration, TimeZone, Utc};
+use serde::Deserialize;
 
-type Result<T> = ::std::result::Result<T, Box<dyn (::std::error::Error)>>;
+use chrono::{Date, Datelike, Duration, NaiveDate, TimeZone, Utc};
+
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
 pub fn todo_filter<'a>(
     todos: impl Iterator<Item = &'a Todo>,
     filters: &[String],
-) -> impl Iterator<Item = &'a Todo> {
-    let (bad, good): (Vec<_>, Vec<_>) = filters
-        .iter()
-        .map(|x| x.to_string())
-        .partition(|x| x.starts_with('-'));
+) -> Result<impl Iterator<Item = &'a Todo>> {
+    let query = Query::parse(filters)?;
+    Ok(todos.filter(move |x| query.matches(x)))
+}
 
-    let bad: Vec<_> = bad.iter().map(|x| x[1..].to_string()).collect();
-    todos.filter(move |x| x.matches(&good, &bad))
+/// Hide tasks whose threshold date is still in the future, unless `show_all`
+pub fn actionable<'a>(
+    todos: impl Iterator<Item = &'a Todo>,
+    show_all: bool,
+) -> impl Iterator<Item = &'a Todo> {
+    todos.filter(move |t| show_all || t.is_actionable())
 }
 
 pub fn notify<T: Display>(message: &str, task: T) {
     println!("{}: {}", message, task);
 }
 
-fn parse_file(filename: &Path) -> Result<Vec<Todo>> {
-    let f =
-        std::fs::File::open(filename).map_err(|_| anyhow!("Couldn't open file {:#?}", filename))?;
-    let reader = BufReader::new(f);
+/// What a file contained when it was read, so that saving can refuse to
+/// overwrite changes made by someone else in the meantime.
+#[derive(Debug, Clone)]
+pub struct FileState {
+    path: PathBuf,
+    contents: Option<String>,
+}
 
-    let mut todos = Vec::new();
-    for (idx, line) in reader.lines().enumerate() {
-        if let Ok(line) = line {
-            let mut todo: Todo = match line.parse() {
-                Ok(todo) => todo,
-                Err(e) => {
-                    eprintln!("Failed to parse todo `{}`: {}`", line, e);
-                    continue;
-                }
-            };
-            todo.idx = idx;
-            todos.push(todo);
+impl FileState {
+    fn read(path: &Path) -> FileState {
+        FileState {
+            path: path.to_path_buf(),
+            contents: fs::read_to_string(path).ok(),
         }
     }
-    Ok(todos)
+
+    pub fn check_unchanged(&self) -> Result<()> {
+        if FileState::read(&self.path).contents != self.contents {
+            return Err(anyhow!(
+                "{:#?} was changed by something else since it was read, so not saving. Re-run the command.",
+                self.path
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_file(filename: &Path) -> Result<(Vec<Todo>, FileState)> {
+    let state = FileState::read(filename);
+    let contents = state.contents.as_ref().ok_or_else(|| {
+        anyhow!(
+            "Couldn't open file {:#?}. Run `t init` to create it.",
+            filename
+        )
+    })?;
+
+    Ok((parse_todos(contents), state))
+}
+
+pub fn parse_todos(contents: &str) -> Vec<Todo> {
+    let mut todos = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let mut todo: Todo = match line.parse() {
+            Ok(todo) => todo,
+            Err(e) => {
+                eprintln!("Failed to parse todo `{}`: {}`", line, e);
+                continue;
+            }
+        };
+        todo.idx = idx;
+        todos.push(todo);
+    }
+    todos
+}
+
+pub fn get_todos() -> Result<(Vec<Todo>, FileState)> {
+    parse_file(config::get().todofile()?)
+}
+
+/// Nothing is done yet if DONEFILE doesn't exist; it's created on first save
+pub fn get_dones() -> Result<(Vec<Todo>, FileState)> {
+    let donefile = config::get().donefile()?;
+    if !donefile.exists() {
+        return Ok((Vec::new(), FileState::read(donefile)));
+    }
+    parse_file(donefile)
+}
+
+/// The contents of `path`, or of stdin if it's `-`
+pub fn read_input(path: &Path) -> Result<String> {
+    if path == Path::new("-") {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents)?;
+        return Ok(contents);
+    }
+    fs::read_to_string(path).map_err(|e| anyhow!("Couldn't read {:#?}: {}", path, e).into())
+}
+
+/// Ask a yes or no question on stdin, taking anything but yes as no
+pub fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Let the user change `text` in `$VISUAL` or `$EDITOR`, or else vi
+pub fn edit_text(text: &str) -> Result<String> {
+    let path = hidden_sibling(config::get().todofile()?, "edit.txt")?;
+    fs::write(&path, text)?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Allow editors with arguments, like `code --wait`
+    let mut words = editor.split_whitespace();
+    let status = match words.next() {
+        Some(program) => Command::new(program).args(words).arg(&path).status(),
+        None => return Err(anyhow!("$EDITOR is empty").into()),
+    };
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    match status {
+        Ok(status) if status.success() => Ok(edited?),
+        Ok(status) => Err(anyhow!("`{}` failed ({}), so nothing changed", editor, status).into()),
+        Err(e) => Err(anyhow!("Couldn't run `{}`: {}", editor, e).into()),
+    }
+}
+
+/// Create an empty file, and any missing directories, unless it's already there
+pub fn create_if_missing(path: &Path) -> Result<bool> {
+    if path.exists() {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    Ok(true)
 }
 
-pub fn get_todos() -> Result<Vec<Todo>> {
-    let todofile = env::var("TODOFILE").map_err(|_| "TODOFILE env var not set")?;
-    parse_file(&PathBuf::from(todofile))
+/// Take an advisory lock, beside `todofile`, that's held until the returned
+/// file is dropped. Other `t` processes wait for it before reading.
+pub fn lock_todo_file(todofile: &Path) -> Result<fs::File> {
+    let f = open_lock_file(todofile)?;
+    if f.try_lock().is_err() {
+        eprintln!("Waiting for another `t` to finish with {:#?}", todofile);
+        f.lock()?;
+    }
+    Ok(f)
 }
 
-pub fn get_dones() -> Result<Vec<Todo>> {
-    let donefile = env::var("DONEFILE").map_err(|_| "DONEFILE env var not set")?;
-    parse_file(&PathBuf::from(donefile))
+/// Like `lock_todo_file`, but fail rather than wait if the file is in use
+pub fn try_lock_todo_file(todofile: &Path) -> Result<fs::File> {
+    let f = open_lock_file(todofile)?;
+    if f.try_lock().is_err() {
+        return Err(anyhow!(
+            "Another `t` is using {:#?}, so not changing it. Try again.",
+            todofile
+        )
+        .into());
+    }
+    Ok(f)
+}
+
+fn open_lock_file(todofile: &Path) -> Result<fs::File> {
+    if !todofile.exists() {
+        return Err(anyhow!(
+            "No todo file at {:#?}. Run `t init` to create it.",
+            todofile
+        )
+        .into());
+    }
+    let lockfile = hidden_sibling(todofile, "lock")?;
+    let f = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lockfile)?;
+    Ok(f)
+}
+
+/// Save tasks to `filename` without ever leaving it half-written.
+///
+/// Whether the file ends with a newline is kept as it was.
+pub fn save_to_file<'a>(todos: impl Iterator<Item = &'a Todo>, path: &Path) -> Result<()> {
+    let trailing_newline = match fs::read(path) {
+        Ok(existing) => existing.last() == Some(&b'\n'),
+        Err(_) => true,
+    };
+    let mut contents = serialise(todos);
+    if trailing_newline {
+        contents.push('\n');
+    }
+    write_atomically(path, &contents)
 }
 
-pub fn save_to_file<'a>(todos: impl Iterator<Item = &'a Todo>, filename: String) -> Result<()> {
-    let f = fs::File::create(filename)?;
-    let mut buf = BufWriter::new(f);
-    write!(
-        buf,
-        "{}",
-        intersperse(todos.map(|x| x.format_for_save()), "\n")
-    )?;
+pub fn serialise<'a>(todos: impl Iterator<Item = &'a Todo>) -> String {
+    intersperse(todos.map(|x| x.format_for_save()), "\n")
+}
+
+/// Write `contents` to a temporary file in the same directory, sync it to
+/// disk, and then rename it over `path`.
+///
+/// If `path` is a symlink, the file it points to is replaced instead, and an
+/// existing file keeps its permissions.
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let path = &fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let permissions = fs::metadata(path).ok().map(|m| m.permissions());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let tmp = hidden_sibling(path, &format!("{}.tmp", std::process::id()))?;
+
+    let write_tmp = || -> Result<()> {
+        let file = fs::File::create(&tmp)?;
+        if let Some(permissions) = &permissions {
+            file.set_permissions(permissions.clone())?;
+        }
+        let mut buf = BufWriter::new(file);
+        write!(buf, "{}", contents)?;
+        buf.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(())
+    };
+    if let Err(e) = write_tmp().and_then(|_| Ok(fs::rename(&tmp, path)?)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    // Make the rename itself durable; not every platform can sync a directory
+    if let Ok(d) = fs::File::open(&dir) {
+        let _ = d.sync_all();
+    }
     Ok(())
 }
 
+/// `dir/.name.suffix` for a file at `dir/name`
+pub fn hidden_sibling(path: &Path, suffix: &str) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Not a file: {:#?}", path))?
+        .to_string_lossy();
+    Ok(path.with_file_name(format!(".{}.{}", name, suffix)))
+}
+
+pub fn should_add_creation_date() -> bool {
+    config::get().creation_date.value
+}
+
+pub fn should_add_id() -> bool {
+    config::get().auto_id.value
+}
+
+pub fn parse_ymd(s: &str) -> Option<Date<Utc>> {
+    let naive = NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+    Some(Date::from_utc(naive, Utc))
+}
+
+/// A `YYYY-MM-DD` date in the configured display format
+pub fn display_date(ymd: &str) -> String {
+    match parse_ymd(ymd) {
+        Some(date) => date.format(&config::get().date_format.value).to_string(),
+        None => ymd.to_string(),
+    }
+}
+
 pub fn date_today() -> Date<Utc> {
     if cfg!(test) {
         // Mon, September 13
@@ -81,23 +293,151 @@ pub fn date_today() -> Date<Utc> {
     }
 }
 
-pub fn parse_date_string_relative(date: Date<Utc>, s: &str) -> String {
-    let to_ymd = |date: Date<Utc>| date.format("%Y-%m-%d").to_string();
-    match s.to_lowercase().as_str() {
-        "today" => to_ymd(date_today()),
-        "tomorrow" => to_ymd(date_today() + Duration::days(1)),
-        "weekend" => to_ymd(iter_till_day_of_week(date, 6)),
-        "monday" | "mon" => to_ymd(iter_till_day_of_week(date, 1)),
-        "tuesday" | "tue" => to_ymd(iter_till_day_of_week(date, 2)),
-        "wednesday" | "wed" => to_ymd(iter_till_day_of_week(date, 3)),
-        "thursday" | "thu" => to_ymd(iter_till_day_of_week(date, 4)),
-        "friday" | "fri" => to_ymd(iter_till_day_of_week(date, 5)),
-        "saturday" | "sat" => to_ymd(iter_till_day_of_week(date, 6)),
-        "sunday" | "sun" => to_ymd(iter_till_day_of_week(date, 7)),
-        _ => date.to_string(),
+pub fn parse_date_string_relative(date: Date<Utc>, s: &str) -> Result<String> {
+    Ok(parse_date(date, s)?.format("%Y-%m-%d").to_string())
+}
+
+/// Understand a date such as `2021-09-20`, `friday`, `next month`, `+3d`,
+/// `2w`, `end of month`, `jan 15` or `15th`, relative to `date`.
+///
+/// Dates without a year, and days of the week or month, are the next
+/// occurrence after `date`.
+pub fn parse_date(date: Date<Utc>, s: &str) -> Result<Date<Utc>> {
+    let lower = s.to_lowercase();
+    let words: Vec<&str> = lower.split_whitespace().collect();
+    let parsed = match words.as_slice() {
+        [] => None,
+        ["today"] => Some(date_today()),
+        ["tomorrow"] => Some(date_today() + Duration::days(1)),
+        ["weekend"] => Some(iter_till_day_of_week(date, 6)),
+        ["next", "week"] => Some(date + Duration::weeks(1)),
+        ["next", "month"] => add_months(date, 1),
+        ["next", "year"] => add_months(date, 12),
+        ["end", "of", "week"] | ["eow"] => {
+            Some(date + Duration::days(7 - date.weekday().number_from_monday() as i64))
+        }
+        ["end", "of", "month"] | ["eom"] => end_of_month(date),
+        ["end", "of", "year"] | ["eoy"] => Some(Utc.ymd(date.year(), 12, 31)),
+        ["next", day] => weekday(day).map(|n| iter_till_day_of_week(date, n)),
+        [day, month] | [month, day] if month_number(month).is_some() => {
+            next_day_of_year(date, month_number(month).unwrap(), day)
+        }
+        ["in", offset] => parse_offset(date, offset),
+        ["in", n, unit] | [n, unit] => parse_offset(date, &format!("{}{}", n, unit)),
+        [word] => weekday(word)
+            .map(|n| iter_till_day_of_week(date, n))
+            .or_else(|| parse_ymd(word))
+            .or_else(|| parse_offset(date, word))
+            .or_else(|| next_day_of_month(date, word)),
+        _ => None,
+    };
+    parsed.ok_or_else(|| {
+        anyhow!(
+            "Couldn't understand the date {:?}. Try a date like 2021-09-20, friday, next month, +3d, 2w, end of month, jan 15 or 15th.",
+            s
+        )
+        .into()
+    })
+}
+
+fn weekday(s: &str) -> Option<u8> {
+    match s {
+        "monday" | "mon" => Some(1),
+        "tuesday" | "tue" => Some(2),
+        "wednesday" | "wed" => Some(3),
+        "thursday" | "thu" => Some(4),
+        "friday" | "fri" => Some(5),
+        "saturday" | "sat" => Some(6),
+        "sunday" | "sun" => Some(7),
+        _ => None,
     }
 }
 
+fn month_number(s: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "january",
+        "february",
+        "march",
+        "april",
+        "may",
+        "june",
+        "july",
+        "august",
+        "september",
+        "october",
+        "november",
+        "december",
+    ];
+    if s.len() < 3 {
+        return None;
+    }
+    MONTHS
+        .iter()
+        .position(|m| m.starts_with(s))
+        .map(|i| i as u32 + 1)
+}
+
+/// An offset such as `3d`, `+2w` or `1 month`
+fn parse_offset(date: Date<Utc>, s: &str) -> Option<Date<Utc>> {
+    let s = s.strip_prefix('+').unwrap_or(s);
+    let digits = s.chars().take_while(|c| c.is_ascii_digit()).count();
+    let n: u32 = s[..digits].parse().ok()?;
+    let days = |d: u32| date.checked_add_signed(Duration::days(d.into()));
+    match &s[digits..] {
+        "d" | "day" | "days" => days(n),
+        "w" | "week" | "weeks" => days(n.checked_mul(7)?),
+        "m" | "month" | "months" => add_months(date, n),
+        "y" | "year" | "years" => add_months(date, n.checked_mul(12)?),
+        _ => None,
+    }
+}
+
+fn parse_day_number(s: &str) -> Option<u32> {
+    let s = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|suffix| s.strip_suffix(suffix))
+        .unwrap_or(s);
+    s.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+/// The next `15th`, skipping months that are too short
+fn next_day_of_month(date: Date<Utc>, s: &str) -> Option<Date<Utc>> {
+    if s.chars().all(|c| c.is_ascii_digit()) {
+        // A bare number is more likely a typo than a day
+        return None;
+    }
+    let day = parse_day_number(s)?;
+    (0..12)
+        .filter_map(|months| {
+            let first = add_months(Utc.ymd(date.year(), date.month(), 1), months)?;
+            Utc.ymd_opt(first.year(), first.month(), day).single()
+        })
+        .find(|d| *d > date)
+}
+
+/// The next `jan 15`, this year or next
+fn next_day_of_year(date: Date<Utc>, month: u32, day: &str) -> Option<Date<Utc>> {
+    let day = parse_day_number(day)?;
+    (0..=4)
+        .filter_map(|years| Utc.ymd_opt(date.year() + years, month, day).single())
+        .find(|d| *d > date)
+}
+
+fn end_of_month(date: Date<Utc>) -> Option<Date<Utc>> {
+    add_months(Utc.ymd(date.year(), date.month(), 1), 1)?.checked_sub_signed(Duration::days(1))
+}
+
+/// Add calendar months to a date, clamping to the last day of shorter months.
+///
+/// `None` if the result is beyond the dates that can be represented.
+pub fn add_months(date: Date<Utc>, months: u32) -> Option<Date<Utc>> {
+    let total = date.year() as i64 * 12 + date.month0() as i64 + months as i64;
+    let (year, month) = (i32::try_from(total / 12).ok()?, (total % 12) as u32 + 1);
+    (1..=date.day())
+        .rev()
+        .find_map(|day| Utc.ymd_opt(year, month, day).single())
+}
+
 fn iter_till_day_of_week(date: Date<Utc>, day_of_week: u8) -> Date<Utc> {
     let mut date = date;
     let one_day = Duration::days(1);
@@ -118,25 +458,52 @@ pub fn join_non_empty(ss: impl Iterator<Item = impl ToString>) -> String {
     intersperse(ss.filter(|x| !x.to_string().is_empty()), " ")
 }
 
+/// How a view orders its tasks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Priority,
+    /// Soonest due first, then by priority
+    Due,
+    /// Oldest first, then by priority
+    Created,
+    /// As they are in the file
+    Index,
+}
+
 pub fn sort_by_priority<'a, I: Iterator<Item = &'a Todo>>(todos: I) -> Vec<Todo> {
+    sort_by(todos, SortOrder::Priority)
+}
+
+pub fn sort_by<'a, I: Iterator<Item = &'a Todo>>(todos: I, order: SortOrder) -> Vec<Todo> {
     let mut todos: Vec<Todo> = todos.cloned().collect();
-    todos.sort_by(|a, b| a.pri.cmp(&b.pri));
+    // Tasks without the date sort last
+    let date_key = |date: &Option<String>| (date.is_none(), date.clone());
+    match order {
+        SortOrder::Priority => todos.sort_by_key(|t| t.pri),
+        SortOrder::Due => todos.sort_by_key(|t| (date_key(&t.due_date), t.pri)),
+        SortOrder::Created => todos.sort_by_key(|t| (date_key(&t.created_date), t.pri)),
+        SortOrder::Index => todos.sort_by_key(|t| t.idx),
+    }
     todos
 }
 
-pub fn open_link(todos: &[Todo], indices: &[usize]) -> Result<()> {
+/// Open the tasks' links without waiting, returning the opener so that the
+/// caller can let it go once the todo file is unlocked
+pub fn open_link(todos: &[Todo], indices: &[usize]) -> Result<std::process::Child> {
     let links: Vec<String> = indices
         .iter()
         .flat_map(|&idx| todos.get(idx).map(|t| t.links()))
         .flatten()
         .collect();
 
-    Command::new("open")
+    let opener = Command::new("open")
         .args(links)
         .spawn()
-        .expect("Failed to open links");
+        .map_err(|e| anyhow!("Failed to open links: {}", e))?;
 
-    Ok(())
+    Ok(opener)
 }
 
 #[cfg(test)]
@@ -157,27 +524,138 @@ mod tests {
 
     #[test]
     fn can_filter() {
-        let input = vec![Todo {
+        let input = [Todo {
             idx: 0,
             task: String::from("This is the task"),
             pri: todo::TodoPriority::None,
             projects: vec![String::from("good"), String::from("bad")],
-            contexts: vec![],
+            tags: vec![],
             done_date: None,
+            created_date: None,
             due_date: None,
+            rec: None,
+            extensions: vec![],
+            source: todo::SourceLine::default(),
         }];
         let expected = vec![];
-        let filtered: Vec<Todo> = todo_filter(input.iter(), &vec![String::from("-bad")])
+        let filtered: Vec<Todo> = todo_filter(input.iter(), &[String::from("-bad")])
+            .unwrap()
             .cloned()
             .collect();
         assert_eq!(filtered, expected);
     }
 
+    #[test]
+    fn save_replaces_file_and_keeps_trailing_newline() {
+        let dir = std::env::temp_dir().join(format!("t-save-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("todo.txt");
+        let todos: Vec<Todo> = vec!["first +p".parse().unwrap(), "second".parse().unwrap()];
+
+        fs::write(&path, "old\n").unwrap();
+        save_to_file(todos.iter(), &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first +p\nsecond\n");
+
+        fs::write(&path, "old").unwrap();
+        save_to_file(todos.iter(), &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first +p\nsecond");
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_follows_symlinks_and_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("t-link-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sync")).unwrap();
+        let (target, link) = (dir.join("sync/todo.txt"), dir.join("todo.txt"));
+        fs::write(&target, "old\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let todos: Vec<Todo> = vec!["new".parse().unwrap()];
+        save_to_file(todos.iter(), &link).unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn notices_files_changed_since_reading() {
+        let dir = std::env::temp_dir().join(format!("t-state-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("todo.txt");
+
+        fs::write(&path, "first\nsecond\n").unwrap();
+        let (todos, state) = parse_file(&path).unwrap();
+        assert_eq!(todos.len(), 2);
+        assert!(state.check_unchanged().is_ok());
+
+        fs::write(&path, "first\nsecond\nthird from elsewhere\n").unwrap();
+        assert!(state.check_unchanged().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn can_add_months() {
+        assert_eq!(
+            add_months(Utc.ymd(2021, 1, 31), 1),
+            Some(Utc.ymd(2021, 2, 28))
+        );
+        assert_eq!(
+            add_months(Utc.ymd(2021, 11, 15), 3),
+            Some(Utc.ymd(2022, 2, 15))
+        );
+        assert_eq!(add_months(Utc.ymd(2021, 11, 15), 9_999_999), None);
+    }
+
     #[test]
     fn date_from_string() {
         let now = date_today();
-        assert_eq!(parse_date_string_relative(now, "thursday"), "2021-09-16");
-        assert_eq!(parse_date_string_relative(now, "tomorrow"), "2021-09-14");
-        assert_eq!(parse_date_string_relative(now, "weekend"), "2021-09-18");
+        let parse = |s| parse_date_string_relative(now, s).unwrap();
+        assert_eq!(parse("thursday"), "2021-09-16");
+        assert_eq!(parse("tomorrow"), "2021-09-14");
+        assert_eq!(parse("weekend"), "2021-09-18");
+        assert_eq!(parse("next friday"), "2021-09-17");
+        assert_eq!(parse("next monday"), "2021-09-20");
+        assert_eq!(parse("+3d"), "2021-09-16");
+        assert_eq!(parse("2w"), "2021-09-27");
+        assert_eq!(parse("in 2 days"), "2021-09-15");
+        assert_eq!(parse("next month"), "2021-10-13");
+        assert_eq!(parse("end of month"), "2021-09-30");
+        assert_eq!(parse("Jan 15"), "2022-01-15");
+        assert_eq!(parse("15 september"), "2021-09-15");
+        assert_eq!(parse("15th"), "2021-09-15");
+        assert_eq!(parse("1st"), "2021-10-01");
+        assert_eq!(parse("31st"), "2021-10-31");
+        assert_eq!(parse("2021-12-01"), "2021-12-01");
+    }
+
+    #[test]
+    fn rejects_unknown_dates() {
+        let now = date_today();
+        for bad in [
+            "nextweek",
+            "",
+            "2021-02-30",
+            "feb 30",
+            "32nd",
+            "15",
+            "3x",
+            "soon",
+            "+9999999m",
+            "4000000000d",
+            "999999999w",
+            "in 99999999 years",
+        ] {
+            assert!(parse_date_string_relative(now, bad).is_err(), "{}", bad);
+        }
     }
 }
@@ -1,14 +1,19 @@
 use super::{
-    todo::Todo,
-    utility::{self, todo_filter},
+    config::{self, OutputFormat, SavedView, ViewCommand},
+    todo::{hierarchy, Record, Todo},
+    utility::{self, todo_filter, SortOrder},
 };
 
 use chrono::Duration;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-type Result<T> = ::std::result::Result<T, Box<dyn (::std::error::Error)>>;
+type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
-pub fn print_todos<'a>(todos: impl Iterator<Item = &'a Todo>) {
+pub fn print_todos<'a>(todos: impl Iterator<Item = &'a Todo>) -> Result<()> {
+    if let Some(format) = structured() {
+        return print_structured(format, &records(todos));
+    }
     println!(
         "{}",
         todos
@@ -16,26 +21,96 @@ pub fn print_todos<'a>(todos: impl Iterator<Item = &'a Todo>) {
             .collect::<Vec<String>>()
             .join("\n")
     );
+    Ok(())
 }
 
-pub fn list<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[String]) -> Result<()> {
-    print_todos(utility::sort_by_priority(todo_filter(todos, filters)).iter());
+/// The `--format` to print views in, unless it's plain text
+fn structured() -> Option<OutputFormat> {
+    match config::get().format.value {
+        OutputFormat::Text => None,
+        format => Some(format),
+    }
+}
 
+fn print_structured<T: Serialize>(format: OutputFormat, items: &[T]) -> Result<()> {
+    if format == OutputFormat::Jsonl {
+        for item in items {
+            println!("{}", serde_json::to_string(item)?);
+        }
+    } else {
+        println!("{}", serde_json::to_string_pretty(items)?);
+    }
     Ok(())
 }
 
-pub fn list_priority<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[String]) -> Result<()> {
-    let sorted = utility::sort_by_priority(
-        todo_filter(todos, filters).filter(|t| !matches!(t.pri, crate::todo::TodoPriority::None)),
+fn records<'a>(todos: impl IntoIterator<Item = &'a Todo>) -> Vec<Record> {
+    todos.into_iter().map(Record::from).collect()
+}
+
+/// Tasks under a heading, such as a date or project, for structured output
+#[derive(Debug, Serialize)]
+struct Group {
+    group: Option<String>,
+    count: usize,
+    tasks: Vec<Record>,
+}
+
+/// How many tasks have a project or tag, for structured output
+#[derive(Debug, Serialize)]
+struct Count {
+    group: Option<String>,
+    count: usize,
+}
+
+pub fn list<'a>(
+    todos: impl Iterator<Item = &'a Todo>,
+    filters: &[String],
+    sort: SortOrder,
+) -> Result<()> {
+    print_todos(utility::sort_by(todo_filter(todos, filters)?, sort).iter())
+}
+
+pub fn list_priority<'a>(
+    todos: impl Iterator<Item = &'a Todo>,
+    filters: &[String],
+    sort: SortOrder,
+) -> Result<()> {
+    let sorted = utility::sort_by(
+        todo_filter(todos, filters)?.filter(|t| !matches!(t.pri, crate::todo::TodoPriority::None)),
+        sort,
     );
-    print_todos(sorted.iter());
-    Ok(())
+    print_todos(sorted.iter())
 }
 
-pub fn done<'a>(dones: impl Iterator<Item = &'a Todo>, filters: &[String]) -> Result<()> {
-    print_todos(utility::sort_by_priority(todo_filter(dones, filters)).iter());
+pub fn done<'a>(
+    dones: impl Iterator<Item = &'a Todo>,
+    filters: &[String],
+    sort: SortOrder,
+) -> Result<()> {
+    print_todos(utility::sort_by(todo_filter(dones, filters)?, sort).iter())
+}
 
-    Ok(())
+/// Run a view from the config file, with any extra filters added to its own
+pub fn saved(
+    view: &SavedView,
+    extra_filters: &[String],
+    todos: &[Todo],
+    dones: &[Todo],
+    show_all: bool,
+) -> Result<()> {
+    let filters: Vec<String> = view.filters.iter().chain(extra_filters).cloned().collect();
+    let actionable = || utility::actionable(todos.iter(), show_all);
+    match view.command {
+        ViewCommand::List => list(actionable(), &filters, view.sort),
+        ViewCommand::ListPriority => list_priority(actionable(), &filters, view.sort),
+        ViewCommand::ListDone => done(dones.iter(), &filters, view.sort),
+        ViewCommand::Due => due(todos.iter(), view.days, &filters),
+        ViewCommand::NoDate => no_date(actionable(), &filters, view.sort),
+        ViewCommand::Upcoming => upcoming(todos.iter(), &filters),
+        ViewCommand::ProjectView => grouped_by_project(actionable(), &filters),
+        ViewCommand::TagView => grouped_by_tag(actionable(), &filters),
+        ViewCommand::Links => links(todos.iter(), &filters),
+    }
 }
 
 pub fn done_summary<'a>(
@@ -46,7 +121,7 @@ pub fn done_summary<'a>(
     let today = utility::date_today();
     let mut last_week = HashMap::new();
 
-    for done in utility::todo_filter(dones, filters) {
+    for done in utility::todo_filter(dones, filters)? {
         let delta = done.days_since_done()?;
         if delta < n_days {
             let entry = last_week.entry(delta).or_insert_with(Vec::new);
@@ -54,11 +129,32 @@ pub fn done_summary<'a>(
         }
     }
 
+    if let Some(format) = structured() {
+        let mut days: Vec<_> = last_week.into_iter().collect();
+        days.sort_by_key(|(delta, _)| -delta);
+        let groups: Vec<Group> = days
+            .into_iter()
+            .map(|(delta, dones)| Group {
+                group: Some(
+                    (today - Duration::days(delta))
+                        .format("%Y-%m-%d")
+                        .to_string(),
+                ),
+                count: dones.len(),
+                tasks: records(&dones),
+            })
+            .collect();
+        return print_structured(format, &groups);
+    }
+
     for i in (0..=n_days).rev() {
         match last_week.get(&i) {
             Some(dones) => {
                 let that_day = today - Duration::days(i);
-                println!("... {} ..........", that_day.format("%Y-%m-%d"));
+                println!(
+                    "... {} ..........",
+                    utility::display_date(&that_day.format("%Y-%m-%d").to_string())
+                );
                 for d in dones {
                     println!("    {}", d.donesummary_format());
                 }
@@ -77,25 +173,41 @@ pub fn due<'a>(
     filters: &[String],
 ) -> Result<()> {
     let mut datediffed_todos = Vec::new();
-    for t in utility::todo_filter(todos, filters) {
+    for t in utility::todo_filter(todos, filters)? {
         if t.due_date.is_some() {
             datediffed_todos.push((t.days_overdue()?, t.to_owned()));
         }
     }
     datediffed_todos.sort_by(|(datediff1, _), (datediff2, _)| datediff2.cmp(datediff1));
+    // Too far in future
+    datediffed_todos.retain(|(days_overdue, _)| {
+        *days_overdue >= 0 || days_overdue.unsigned_abs() as usize <= n_days
+    });
+    if let Some(format) = structured() {
+        #[derive(Serialize)]
+        struct Due {
+            days_overdue: i64,
+            #[serde(flatten)]
+            task: Record,
+        }
+        let due: Vec<Due> = datediffed_todos
+            .iter()
+            .map(|(days_overdue, t)| Due {
+                days_overdue: *days_overdue,
+                task: Record::from(t),
+            })
+            .collect();
+        return print_structured(format, &due);
+    }
     let mut prev = None;
     for (days_overdue, t) in datediffed_todos {
         let days_in_future: usize = days_overdue.unsigned_abs() as usize;
-        if days_overdue < 0 && days_in_future > n_days {
-            // Too far in future
-            continue;
-        }
         let header = match days_overdue {
             0 => "Today".to_string(),
             1.. => format!("Overdue {} days", days_overdue),
             _ => format!("In {} days", days_in_future),
         };
-        let header = format!("..... {} {}", header, ".".repeat(73 - header.len()));
+        let header = group_header(&header);
         match prev {
             Some(p) => {
                 if days_overdue != p {
@@ -110,49 +222,95 @@ pub fn due<'a>(
     Ok(())
 }
 
-pub fn no_date<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[String]) -> Result<()> {
-    let undated_todos = todo_filter(todos, filters).filter(|todo| todo.due_date.is_none());
-    for todo in utility::sort_by_priority(undated_todos) {
-        println!("{}", todo);
-    }
-    Ok(())
+pub fn no_date<'a>(
+    todos: impl Iterator<Item = &'a Todo>,
+    filters: &[String],
+    sort: SortOrder,
+) -> Result<()> {
+    let undated_todos = todo_filter(todos, filters)?.filter(|todo| todo.due_date.is_none());
+    print_todos(utility::sort_by(undated_todos, sort).iter())
 }
 
-pub fn no_projects<'a>(todos: impl Iterator<Item = &'a Todo>) -> Result<()> {
-    for t in todos {
-        if t.projects.is_empty() {
-            println!("{}", t);
+/// A dotted line introducing a group of tasks, however long its header
+fn group_header(header: &str) -> String {
+    let dots = 73usize.saturating_sub(header.len());
+    format!("..... {} {}", header, ".".repeat(dots))
+}
+
+pub fn upcoming<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[String]) -> Result<()> {
+    let today = utility::date_today();
+    let mut by_threshold = BTreeMap::new();
+    for t in todo_filter(todos, filters)? {
+        if let Some(threshold) = t.threshold_date().filter(|&d| d > today) {
+            let entry = by_threshold
+                .entry(threshold.naive_utc())
+                .or_insert_with(Vec::new);
+            entry.push(t);
         }
     }
+    if let Some(format) = structured() {
+        let groups: Vec<Group> = by_threshold
+            .into_iter()
+            .map(|(threshold, todos_for_date)| Group {
+                group: Some(threshold.format("%Y-%m-%d").to_string()),
+                count: todos_for_date.len(),
+                tasks: records(utility::sort_by_priority(todos_for_date.into_iter()).iter()),
+            })
+            .collect();
+        return print_structured(format, &groups);
+    }
+    for (threshold, todos_for_date) in by_threshold {
+        let header = format!(
+            "{} (in {} days)",
+            utility::display_date(&threshold.format("%Y-%m-%d").to_string()),
+            (threshold - today.naive_utc()).num_days()
+        );
+        println!("{}", group_header(&header));
+        print_todos(utility::sort_by_priority(todos_for_date.into_iter()).iter())?;
+        println!();
+    }
     Ok(())
 }
 
+pub fn no_projects<'a>(todos: impl Iterator<Item = &'a Todo>) -> Result<()> {
+    print_todos(todos.filter(|t| t.projects.is_empty()))
+}
+
 pub fn projects<'a>(todos: impl Iterator<Item = &'a Todo>) -> Result<()> {
-    let mut projects = HashMap::new();
+    let mut tree = ProjectTree::default();
     let mut n_no_project = 0;
     for t in todos {
         if t.projects.is_empty() {
             n_no_project += 1;
         }
-        for project in &t.projects {
-            let entry = projects.entry(project).or_insert(0);
-            *entry += 1;
-        }
+        tree.insert(t);
+    }
+    if let Some(format) = structured() {
+        let mut counts: Vec<Count> = tree
+            .flatten()
+            .into_iter()
+            .map(|(name, node)| Count {
+                group: Some(name),
+                count: node.count(),
+            })
+            .collect();
+        counts.push(Count {
+            group: None,
+            count: n_no_project,
+        });
+        return print_structured(format, &counts);
     }
-    for (p, n) in projects {
-        println!("{} {}", p, n);
+    for (name, node) in &tree.children {
+        node.walk(name, 0, &mut |indent, name, node| {
+            println!("{}{} {}", indent, name, node.count());
+        });
     }
     println!("NO PROJECT {}", n_no_project);
     Ok(())
 }
 
 pub fn no_tags<'a>(todos: impl Iterator<Item = &'a Todo>) -> Result<()> {
-    for t in todos {
-        if t.tags.is_empty() {
-            println!("{}", t);
-        }
-    }
-    Ok(())
+    print_todos(todos.filter(|t| t.tags.is_empty()))
 }
 
 pub fn tags<'a>(todos: impl Iterator<Item = &'a Todo>) -> Result<()> {
@@ -168,6 +326,21 @@ pub fn tags<'a>(todos: impl Iterator<Item = &'a Todo>) -> Result<()> {
             *entry += 1;
         }
     }
+    if let Some(format) = structured() {
+        let mut counts: Vec<Count> = tags
+            .into_iter()
+            .map(|(tag, count)| Count {
+                group: Some(tag.to_string()),
+                count,
+            })
+            .collect();
+        counts.sort_by(|a, b| a.group.cmp(&b.group));
+        counts.push(Count {
+            group: None,
+            count: n_no_tag,
+        });
+        return print_structured(format, &counts);
+    }
     for (c, n) in tags {
         println!("{} {}", c, n);
     }
@@ -179,24 +352,40 @@ pub fn grouped_by_project<'a>(
     todos: impl Iterator<Item = &'a Todo>,
     filters: &[String],
 ) -> Result<()> {
-    let mut projects = HashMap::new();
+    let mut tree = ProjectTree::default();
     let mut no_project = Vec::new();
-    let sorted_and_filtered = utility::sort_by_priority(todo_filter(todos, filters));
+    let sorted_and_filtered = utility::sort_by_priority(todo_filter(todos, filters)?);
     for t in &sorted_and_filtered {
         if t.projects.is_empty() {
             no_project.push(t);
         } else {
-            for project in &t.projects {
-                let entry = projects.entry(project).or_insert(vec![]);
-                (*entry).push(t);
-            }
+            tree.insert(t);
         }
     }
-    for (p, todos_for_project) in projects {
-        println!("{}", p);
-        for todo in todos_for_project {
-            println!("{}", todo);
-        }
+    if let Some(format) = structured() {
+        let mut groups: Vec<Group> = tree
+            .flatten()
+            .into_iter()
+            .map(|(name, node)| Group {
+                group: Some(name),
+                count: node.count(),
+                tasks: records(node.todos.iter().copied()),
+            })
+            .collect();
+        groups.push(Group {
+            group: None,
+            count: no_project.len(),
+            tasks: records(no_project),
+        });
+        return print_structured(format, &groups);
+    }
+    for (name, node) in &tree.children {
+        node.walk(name, 0, &mut |indent, name, node| {
+            println!("{}{} ({})", indent, name, node.count());
+            for todo in &node.todos {
+                println!("{}{}", indent, todo);
+            }
+        });
         println!();
     }
     println!("NO PROJECT");
@@ -207,10 +396,57 @@ pub fn grouped_by_project<'a>(
     Ok(())
 }
 
+/// Projects nested by their `.` or `/` separated hierarchy, with the tasks
+/// directly in each one.
+#[derive(Debug, Default)]
+struct ProjectTree<'a> {
+    children: BTreeMap<String, ProjectTree<'a>>,
+    todos: Vec<&'a Todo>,
+    // Every task in this project or beneath it, counted once each
+    all: BTreeSet<usize>,
+}
+
+impl<'a> ProjectTree<'a> {
+    fn insert(&mut self, todo: &'a Todo) {
+        for project in &todo.projects {
+            let mut node = &mut *self;
+            for part in hierarchy(project) {
+                node = node.children.entry(part.to_string()).or_default();
+                node.all.insert(todo.idx);
+            }
+            node.todos.push(todo);
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.all.len()
+    }
+
+    /// Every project beneath this one, by its full name, in the order `walk` visits them
+    fn flatten(&self) -> Vec<(String, &ProjectTree<'a>)> {
+        let mut out = Vec::new();
+        for (name, child) in &self.children {
+            out.push((name.clone(), child));
+            for (child_name, grandchild) in child.flatten() {
+                out.push((format!("{}{}", name, child_name), grandchild));
+            }
+        }
+        out
+    }
+
+    /// Visit this project and everything beneath it, with indentation for depth
+    fn walk(&self, name: &str, depth: usize, f: &mut impl FnMut(&str, &str, &ProjectTree)) {
+        f(&"    ".repeat(depth), name, self);
+        for (child_name, child) in &self.children {
+            child.walk(child_name, depth + 1, f);
+        }
+    }
+}
+
 pub fn grouped_by_tag<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[String]) -> Result<()> {
     let mut tags = HashMap::new();
     let mut no_tag = Vec::new();
-    let sorted_and_filtered = utility::sort_by_priority(todo_filter(todos, filters));
+    let sorted_and_filtered = utility::sort_by_priority(todo_filter(todos, filters)?);
     for t in &sorted_and_filtered {
         if t.tags.is_empty() {
             no_tag.push(t);
@@ -221,6 +457,23 @@ pub fn grouped_by_tag<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[Stri
             }
         }
     }
+    if let Some(format) = structured() {
+        let mut groups: Vec<Group> = tags
+            .into_iter()
+            .map(|(tag, todos_for_tag)| Group {
+                group: Some(tag.to_string()),
+                count: todos_for_tag.len(),
+                tasks: records(todos_for_tag),
+            })
+            .collect();
+        groups.sort_by(|a, b| a.group.cmp(&b.group));
+        groups.push(Group {
+            group: None,
+            count: no_tag.len(),
+            tasks: records(no_tag),
+        });
+        return print_structured(format, &groups);
+    }
     for (c, todos_for_tag) in tags {
         println!("{}", c);
         for todo in todos_for_tag {
@@ -238,7 +491,23 @@ pub fn grouped_by_tag<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[Stri
 
 pub fn links<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[String]) -> Result<()> {
     let filtered =
-        todo_filter(todos, filters).filter(|t| !matches!(t.pri, crate::todo::TodoPriority::None));
+        todo_filter(todos, filters)?.filter(|t| !matches!(t.pri, crate::todo::TodoPriority::None));
+    if let Some(format) = structured() {
+        #[derive(Serialize)]
+        struct Links {
+            links: Vec<String>,
+            #[serde(flatten)]
+            task: Record,
+        }
+        let links: Vec<Links> = filtered
+            .filter(|t| !t.links().is_empty())
+            .map(|t| Links {
+                links: t.links(),
+                task: Record::from(t),
+            })
+            .collect();
+        return print_structured(format, &links);
+    }
     for t in filtered {
         let l = t.links();
         if !l.is_empty() {
@@ -250,3 +519,60 @@ pub fn links<'a>(todos: impl Iterator<Item = &'a Todo>, filters: &[String]) -> R
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_headers_allow_long_date_formats() {
+        let date = utility::parse_ymd("2021-09-20").unwrap();
+        let long = date.format(&"%A %d %B %Y ".repeat(5)).to_string();
+        let header = group_header(&format!("{} (in 7 days)", long));
+        assert!(header.ends_with("(in 7 days) "));
+        assert_eq!(group_header("Today").len(), 6 + 5 + 1 + 68);
+    }
+
+    #[test]
+    fn project_tree_rolls_up_counts() {
+        let todos = utility::parse_todos(
+            "a +work.clientA.billing\nb +work.clientA +work.clientB\nc +work\nd +home",
+        );
+        let mut tree = ProjectTree::default();
+        todos.iter().for_each(|t| tree.insert(t));
+
+        let mut seen = Vec::new();
+        for (name, node) in &tree.children {
+            node.walk(name, 0, &mut |indent, name, node| {
+                seen.push(format!("{}{} {}", indent, name, node.count()));
+                seen.extend(node.todos.iter().map(|t| format!("{}{}", indent, t.task)));
+            });
+        }
+        assert_eq!(
+            seen,
+            vec![
+                "+home 1",
+                "d",
+                "+work 3",
+                "c",
+                "    .clientA 2",
+                "    b",
+                "        .billing 1",
+                "        a",
+                "    .clientB 1",
+                "    b",
+            ]
+        );
+        let names: Vec<String> = tree.flatten().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "+home",
+                "+work",
+                "+work.clientA",
+                "+work.clientA.billing",
+                "+work.clientB"
+            ]
+        );
+    }
+}